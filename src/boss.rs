@@ -0,0 +1,248 @@
+use bevy::prelude::*;
+
+use crate::{AppState, ColorblindMode, GameConfig, GameMode, Materials, Paused, SCALE, Speed, TimeScale, WindowSize, enemy::{ActiveEnemies, Enemy, Health, spawn_enemy_laser}, gameover::Continuing};
+
+const BOSS_HEALTH: u32 = 40;
+const BOSS_SCALE: f32 = SCALE * 2.5;
+const BOSS_SPEED: f32 = 150.;
+const BOSS_FIRE_INTERVAL: f32 = 1.2;
+const BOSS_SPREAD_LASERS: u32 = 5;
+const BOSS_SPREAD_ANGLE: f32 = 0.9;
+// extra health per boss rush round
+const BOSS_HEALTH_STEP: u32 = 20;
+const BOSS_RUSH_ROUNDS: u32 = 5;
+// breather before each boss rush boss, and before the victory screen
+const BOSS_RUSH_DELAY: f32 = 2.;
+// after the regular enemies on the enemy sheet
+const BOSS_FRAME: u32 = 3;
+// the core glowing under the boss's nose, in world units from its center
+const WEAK_POINT_OFFSET_Y: f32 = -25.;
+const WEAK_POINT_RADIUS: f32 = 14.;
+// radians per second, and how far the glow swells either way
+const WEAK_POINT_PULSE_RATE: f32 = 6.;
+const WEAK_POINT_PULSE: f32 = 0.25;
+
+pub struct Boss;
+// queued by the wave system or the boss rush, turned into the boss next frame
+pub struct BossToSpawn {
+    pub level: u32
+}
+// a child of the boss, lasers landing inside it do extra damage
+pub struct WeakPoint {
+    pub offset: Vec3,
+    pub radius: f32
+}
+
+impl WeakPoint {
+    // the offset is in world units, so the boss's own scale doesn't stretch it
+    pub fn contains(&self, boss_tf: &Transform, point: Vec3) -> bool {
+        (point - (boss_tf.translation + self.offset)).truncate().length() <= self.radius
+    }
+}
+
+// how far into the boss rush the boss came, 0 for the regular wave bosses
+struct BossLevel(u32);
+struct BossRush {
+    round: u32,
+    delay: Timer
+}
+
+impl Default for BossRush {
+    fn default() -> Self {
+        Self {
+            round: 0,
+            delay: Timer::from_seconds(BOSS_RUSH_DELAY, false)
+        }
+    }
+}
+
+impl BossLevel {
+    fn health(&self) -> Health {
+        Health::new(BOSS_HEALTH + BOSS_HEALTH_STEP * self.0)
+    }
+
+    fn speed(&self) -> Speed {
+        Speed(BOSS_SPEED * (1. + 0.15 * self.0 as f32))
+    }
+
+    fn fire_interval(&self) -> f32 {
+        BOSS_FIRE_INTERVAL * 0.85_f32.powi(self.0 as i32)
+    }
+
+    fn spread_lasers(&self) -> u32 {
+        BOSS_SPREAD_LASERS + self.0
+    }
+}
+
+// time between volleys, scaled like the other enemies' fire
+struct BossFire(Timer);
+struct BossMovement {
+    entering: bool,
+    dir: f32
+}
+
+pub struct BossPlugin;
+
+impl Plugin for BossPlugin {
+    fn build(&self, app: &mut AppBuilder) {
+        app
+            .insert_resource(BossRush::default())
+            .add_system_set(
+                SystemSet::on_enter(AppState::InGame)
+                .with_system(reset_boss_rush.system())
+            )
+            .add_system_set(
+                SystemSet::on_update(AppState::InGame)
+                .with_system(boss_to_spawn.system())
+                .with_system(boss_movement.system())
+                .with_system(boss_fire.system())
+                .with_system(boss_rush_spawn.system())
+                .with_system(pulse_weak_point.system())
+            );
+    }
+}
+
+fn boss_to_spawn(
+    mut commands: Commands,
+    materials: Res<Materials>,
+    window_size: Res<WindowSize>,
+    query: Query<(Entity, &BossToSpawn)>
+) {
+    for (entity, boss_to_spawn) in query.iter() {
+        let level = BossLevel(boss_to_spawn.level);
+        // enter from the top center
+        commands
+            .spawn_bundle(SpriteSheetBundle {
+                texture_atlas: materials.enemies.clone(),
+                sprite: TextureAtlasSprite::new(BOSS_FRAME),
+                transform: Transform {
+                    translation: Vec3::new(0., window_size.height / 2. + 100., 10.),
+                    scale: Vec3::new(BOSS_SCALE, BOSS_SCALE, 1.),
+                    ..Default::default()
+                },
+                ..Default::default()
+            })
+            .insert(Enemy)
+            .insert(Boss)
+            .insert(level.health())
+            .insert(level.speed())
+            .insert(BossFire(Timer::from_seconds(level.fire_interval(), true)))
+            .insert(level)
+            .insert(BossMovement { entering: true, dir: 1. })
+            .with_children(|parent| {
+                let offset = Vec3::new(0., WEAK_POINT_OFFSET_Y, 0.);
+                parent.spawn_bundle(SpriteBundle {
+                    material: materials.weak_point.clone(),
+                    sprite: Sprite::new(Vec2::splat(WEAK_POINT_RADIUS * 2. / BOSS_SCALE)),
+                    // children are placed in the boss's scaled space
+                    transform: Transform::from_translation(offset / BOSS_SCALE + Vec3::Z),
+                    ..Default::default()
+                })
+                .insert(WeakPoint { offset, radius: WEAK_POINT_RADIUS });
+            });
+
+        commands.entity(entity).despawn();
+    }
+}
+
+fn pulse_weak_point(time: Res<Time>, paused: Res<Paused>, mut query: Query<&mut Transform, With<WeakPoint>>) {
+    if paused.0 {
+        return;
+    }
+    let pulse = 1. + WEAK_POINT_PULSE * (time.seconds_since_startup() as f32 * WEAK_POINT_PULSE_RATE).sin();
+    for mut tf in query.iter_mut() {
+        tf.scale = Vec3::new(pulse, pulse, 1.);
+    }
+}
+
+fn boss_movement(
+    time: Res<Time>,
+    time_scale: Res<TimeScale>,
+    paused: Res<Paused>,
+    window_size: Res<WindowSize>,
+    mut query: Query<(&mut Transform, &Speed, &mut BossMovement), With<Boss>>
+) {
+    if paused.0 {
+        return;
+    }
+    let hover_y = window_size.height / 2. - 120.;
+    let limit = (window_size.width / 2. - 100.).max(0.);
+    for (mut tf, speed, mut movement) in query.iter_mut() {
+        let step = speed.0 * time.delta_seconds() * time_scale.0;
+        if movement.entering {
+            tf.translation.y = (tf.translation.y - step).max(hover_y);
+            movement.entering = tf.translation.y > hover_y;
+        } else {
+            // sweep side to side, bouncing off the edges
+            tf.translation.x += movement.dir * step;
+            if tf.translation.x > limit {
+                tf.translation.x = limit;
+                movement.dir = -1.;
+            } else if tf.translation.x < -limit {
+                tf.translation.x = -limit;
+                movement.dir = 1.;
+            }
+        }
+    }
+}
+
+fn boss_fire(
+    mut commands: Commands,
+    materials: Res<Materials>,
+    config: Res<GameConfig>,
+    colorblind: Res<ColorblindMode>,
+    paused: Res<Paused>,
+    time: Res<Time>,
+    time_scale: Res<TimeScale>,
+    mut query: Query<(&Transform, &BossMovement, &BossLevel, &mut BossFire), With<Boss>>
+) {
+    if paused.0 {
+        return;
+    }
+    for (tf, movement, level, mut fire) in query.iter_mut() {
+        if movement.entering || !fire.0.tick(time.delta().mul_f32(time_scale.0)).just_finished() {
+            continue;
+        }
+        // fan the lasers out evenly around straight down
+        let lasers = level.spread_lasers();
+        for i in 0..lasers {
+            let angle = -BOSS_SPREAD_ANGLE / 2. + BOSS_SPREAD_ANGLE * i as f32 / (lasers - 1) as f32;
+            let dir = Vec2::new(angle.sin(), -angle.cos());
+            spawn_enemy_laser(&mut commands, materials.enemy_laser(&colorblind), Vec3::new(tf.translation.x, tf.translation.y - 40., 0.), dir, config.enemy_laser_speed);
+        }
+    }
+}
+
+fn reset_boss_rush(mut boss_rush: ResMut<BossRush>, continuing: Res<Continuing>) {
+    if continuing.0 {
+        return;
+    }
+    *boss_rush = BossRush::default();
+}
+
+// in boss rush the next, tougher boss comes in once the last one is gone
+fn boss_rush_spawn(
+    mut commands: Commands,
+    game_mode: Res<GameMode>,
+    paused: Res<Paused>,
+    time: Res<Time>,
+    mut app_state: ResMut<State<AppState>>,
+    mut active_enemies: ResMut<ActiveEnemies>,
+    mut boss_rush: ResMut<BossRush>
+) {
+    if *game_mode != GameMode::BossRush || paused.0 || active_enemies.0 > 0 {
+        return;
+    }
+    if !boss_rush.delay.tick(time.delta()).finished() {
+        return;
+    }
+    boss_rush.delay.reset();
+    if boss_rush.round >= BOSS_RUSH_ROUNDS {
+        // a death on the same frame may already have queued game over, see hit_player
+        let _ = app_state.set(AppState::Victory);
+        return;
+    }
+    commands.spawn().insert(BossToSpawn { level: boss_rush.round });
+    active_enemies.0 += 1;
+    boss_rush.round += 1;
+}
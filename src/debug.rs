@@ -0,0 +1,226 @@
+use std::collections::{HashMap, HashSet};
+
+use bevy::{diagnostic::{Diagnostics, FrameTimeDiagnosticsPlugin}, prelude::*};
+
+use crate::{Explosion, FONT, frame_size, enemy::Enemy, player::{Laser, Player}};
+
+const HITBOX_THICKNESS: f32 = 1.;
+
+struct FpsText;
+// refreshed on its own timer rather than every frame
+struct EntityCountText(Timer);
+#[derive(Default)]
+struct ShowHitboxes(bool);
+struct HitboxMaterial(Handle<ColorMaterial>);
+// one side of the outline drawn around `target`'s collision box
+struct HitboxEdge {
+    target: Entity,
+    side: HitboxSide
+}
+#[derive(Clone, Copy)]
+enum HitboxSide {
+    Top,
+    Bottom,
+    Left,
+    Right
+}
+pub struct DebugPlugin;
+
+impl Plugin for DebugPlugin {
+    fn build(&self, app: &mut AppBuilder) {
+        app
+            .add_plugin(FrameTimeDiagnosticsPlugin::default())
+            .add_startup_system(setup_fps_text.system())
+            .add_system(update_fps_text.system())
+            .add_system(toggle_fps_text.system())
+            .add_startup_system(setup_entity_count_text.system())
+            .add_system(update_entity_count_text.system())
+            .add_system(toggle_entity_count_text.system())
+            .insert_resource(ShowHitboxes::default())
+            .add_startup_system(setup_hitbox_material.system())
+            .add_system(toggle_hitboxes.system())
+            .add_system(show_hitboxes.system());
+    }
+}
+
+fn setup_fps_text(mut commands: Commands, asset_server: Res<AssetServer>) {
+    // sits under the lives icons, clear of the score on the left
+    commands.spawn_bundle(TextBundle {
+        style: Style {
+            position_type: PositionType::Absolute,
+            position: Rect {
+                top: Val::Px(50.),
+                right: Val::Px(10.),
+                ..Default::default()
+            },
+            ..Default::default()
+        },
+        text: Text::with_section(
+            "FPS: --",
+            TextStyle {
+                font: asset_server.load(FONT),
+                font_size: 16.,
+                color: Color::YELLOW
+            },
+            Default::default()
+        ),
+        visible: Visible {
+            is_visible: false,
+            is_transparent: true
+        },
+        ..Default::default()
+    })
+    .insert(FpsText);
+}
+
+fn update_fps_text(diagnostics: Res<Diagnostics>, mut query: Query<(&mut Text, &Visible), With<FpsText>>) {
+    let fps = diagnostics
+        .get(FrameTimeDiagnosticsPlugin::FPS)
+        .and_then(|fps| fps.average());
+    for (mut text, visible) in query.iter_mut() {
+        if let (true, Some(fps)) = (visible.is_visible, fps) {
+            text.sections[0].value = format!("FPS: {:.0}", fps);
+        }
+    }
+}
+
+fn toggle_fps_text(keyboard_input: Res<Input<KeyCode>>, mut query: Query<&mut Visible, With<FpsText>>) {
+    if keyboard_input.just_pressed(KeyCode::F3) {
+        for mut visible in query.iter_mut() {
+            visible.is_visible = !visible.is_visible;
+        }
+    }
+}
+
+fn setup_entity_count_text(mut commands: Commands, asset_server: Res<AssetServer>) {
+    commands.spawn_bundle(TextBundle {
+        style: Style {
+            position_type: PositionType::Absolute,
+            position: Rect {
+                top: Val::Px(70.),
+                right: Val::Px(10.),
+                ..Default::default()
+            },
+            ..Default::default()
+        },
+        text: Text::with_section(
+            "",
+            TextStyle {
+                font: asset_server.load(FONT),
+                font_size: 16.,
+                color: Color::YELLOW
+            },
+            Default::default()
+        ),
+        visible: Visible {
+            is_visible: false,
+            is_transparent: true
+        },
+        ..Default::default()
+    })
+    .insert(EntityCountText(Timer::from_seconds(1., true)));
+}
+
+fn update_entity_count_text(
+    time: Res<Time>,
+    laser_query: Query<(), With<Laser>>,
+    enemy_query: Query<(), With<Enemy>>,
+    explosion_query: Query<(), With<Explosion>>,
+    mut query: Query<(&mut Text, &Visible, &mut EntityCountText)>
+) {
+    for (mut text, visible, mut count_text) in query.iter_mut() {
+        if count_text.0.tick(time.delta()).just_finished() && visible.is_visible {
+            text.sections[0].value = format!(
+                "lasers: {}  enemies: {}  explosions: {}",
+                laser_query.iter().count(),
+                enemy_query.iter().count(),
+                explosion_query.iter().count()
+            );
+        }
+    }
+}
+
+fn toggle_entity_count_text(keyboard_input: Res<Input<KeyCode>>, mut query: Query<&mut Visible, With<EntityCountText>>) {
+    if keyboard_input.just_pressed(KeyCode::F4) {
+        for mut visible in query.iter_mut() {
+            visible.is_visible = !visible.is_visible;
+        }
+    }
+}
+
+fn setup_hitbox_material(mut commands: Commands, mut materials: ResMut<Assets<ColorMaterial>>) {
+    commands.insert_resource(HitboxMaterial(materials.add(Color::rgb(0.2, 1., 0.2).into())));
+}
+
+fn toggle_hitboxes(keyboard_input: Res<Input<KeyCode>>, mut show: ResMut<ShowHitboxes>) {
+    if keyboard_input.just_pressed(KeyCode::F5) {
+        show.0 = !show.0;
+    }
+}
+
+impl HitboxSide {
+    const ALL: [HitboxSide; 4] = [HitboxSide::Top, HitboxSide::Bottom, HitboxSide::Left, HitboxSide::Right];
+
+    // center and size of this side for a box centered on `center`
+    fn place(&self, center: Vec2, size: Vec2) -> (Vec2, Vec2) {
+        let half = size / 2.;
+        match self {
+            HitboxSide::Top => (center + Vec2::new(0., half.y), Vec2::new(size.x, HITBOX_THICKNESS)),
+            HitboxSide::Bottom => (center - Vec2::new(0., half.y), Vec2::new(size.x, HITBOX_THICKNESS)),
+            HitboxSide::Left => (center - Vec2::new(half.x, 0.), Vec2::new(HITBOX_THICKNESS, size.y)),
+            HitboxSide::Right => (center + Vec2::new(half.x, 0.), Vec2::new(HITBOX_THICKNESS, size.y))
+        }
+    }
+}
+
+// outlines the same box aabb_collides tests, including the abs() on flipped scales
+fn show_hitboxes(
+    mut commands: Commands,
+    show: Res<ShowHitboxes>,
+    material: Res<HitboxMaterial>,
+    texture_atlases: Res<Assets<TextureAtlas>>,
+    target_query: Query<(Entity, &Transform, &Sprite), (Or<(With<Player>, With<Laser>)>, Without<HitboxEdge>)>,
+    // enemies are drawn from the sprite sheet
+    enemy_query: Query<(Entity, &Transform, &TextureAtlasSprite, &Handle<TextureAtlas>), (With<Enemy>, Without<HitboxEdge>)>,
+    mut edge_query: Query<(Entity, &HitboxEdge, &mut Transform, &mut Sprite)>
+) {
+    let boxes: HashMap<Entity, (Vec2, Vec2)> = if show.0 {
+        target_query
+            .iter()
+            .map(|(entity, tf, sprite)| (entity, tf, sprite.size))
+            .chain(enemy_query.iter().map(|(entity, tf, sprite, atlas)| (entity, tf, frame_size(&texture_atlases, atlas, sprite))))
+            .map(|(entity, tf, size)| (entity, (tf.translation.into(), size * Vec2::from(tf.scale.abs()))))
+            .collect()
+    } else {
+        HashMap::new()
+    };
+
+    let mut outlined = HashSet::new();
+    for (edge_entity, edge, mut tf, mut sprite) in edge_query.iter_mut() {
+        match boxes.get(&edge.target) {
+            Some(&(center, size)) => {
+                let (position, edge_size) = edge.side.place(center, size);
+                tf.translation.x = position.x;
+                tf.translation.y = position.y;
+                sprite.size = edge_size;
+                outlined.insert(edge.target);
+            }
+            None => commands.entity(edge_entity).despawn()
+        }
+    }
+
+    for (&target, &(center, size)) in boxes.iter().filter(|(target, _)| !outlined.contains(*target)) {
+        for side in HitboxSide::ALL {
+            let (position, edge_size) = side.place(center, size);
+            commands
+                .spawn_bundle(SpriteBundle {
+                    material: material.0.clone(),
+                    sprite: Sprite::new(edge_size),
+                    // over the gameplay sprites, under the screen flash
+                    transform: Transform::from_xyz(position.x, position.y, 800.),
+                    ..Default::default()
+                })
+                .insert(HitboxEdge { target, side });
+        }
+    }
+}
@@ -1,25 +1,255 @@
-use std::{f32::consts::PI};
+use std::{collections::{HashMap, HashSet}, time::Duration};
 
 use bevy::{core::{FixedTimestep}, prelude::*};
-use rand::{Rng, thread_rng};
+use rand::{Rng, seq::SliceRandom};
 
-use crate::{Materials, SCALE, Speed, TIME_PER_FRAME, WindowSize, player::Laser};
+use crate::{AppState, ColorblindMode, Difficulty, GameConfig, GameMode, GameRng, Materials, Paused, RunStats, SCALE, Speed, TimeScale, WindowSize, boss::BossToSpawn, gameover::Continuing, in_game_or_menu, spawn_muzzle_flash, player::{Laser, Player}};
 
-const MAX_ENEMIES: u32 = 5;
-const MAX_FORMATION_MEMBERS: u32 = 2;
+// formations start out as pairs and grow by one every other wave
+const MIN_FORMATION_MEMBERS: u32 = 2;
+const MAX_FORMATION_MEMBERS: u32 = 6;
+const WAVE_BASE_BUDGET: u32 = 10;
+const WAVE_BUDGET_STEP: u32 = 5;
+const ENEMY_FIRE_INTERVAL: f64 = 0.9;
+const BOSS_WAVE_INTERVAL: u32 = 5;
+// enemies allowed to shoot per volley
+const MAX_SHOOTERS: usize = 2;
+const MIN_SPAN: f32 = 1.;
+// chance per second that an enemy in formation breaks off to dive
+const DIVE_CHANCE: f64 = 0.08;
+// horizontal gap between neighbouring slots of a formation
+const FORMATION_SLOT_SPACING: f32 = 60.;
+// how far a formation sweeps to either side, and how fast (radians per second)
+const FORMATION_SWAY: f32 = 60.;
+const FORMATION_SWAY_RATE: f32 = 1.2;
+// angle between the side lasers of a spread volley and the middle one
+const ENEMY_SPREAD_ANGLE: f32 = 0.3;
+// splitter children are smaller than a regular grunt
+const SPLIT_SCALE: f32 = SCALE * 0.7;
+const SPLIT_OFFSET: f32 = 15.;
+// a berserker at death's door moves this much faster per bit of health lost, up to the cap
+const BERSERK_RAGE: f32 = 1.5;
+const BERSERK_MAX_SPEED: f32 = 2.;
+// enemies this close to a guardian can't be hurt
+const GUARDIAN_RADIUS: f32 = 120.;
+const GUARDIAN_BUBBLE_SCALE: f32 = 0.6;
+const PRACTICE_GRID: u32 = 3;
+const PRACTICE_SPACING: f32 = 100.;
+// how long the edge arrow shows before the enemy flies in
+const SPAWN_WARNING_TIME: f32 = 0.6;
+const SPAWN_WARNING_BLINK: f32 = 0.1;
+const SPAWN_WARNING_INSET: f32 = 20.;
+// frames on the enemy sheet, the boss takes the last one
+const GRUNT_FRAME: u32 = 0;
+const DIVER_FRAME: u32 = 1;
+const TANK_FRAME: u32 = 2;
 pub struct ActiveEnemies(pub u32);
+// time between enemy volleys
+pub struct EnemyFireTimer(pub Timer);
+pub struct Wave(pub u32);
+// progress through the current wave
+#[derive(Default)]
+pub struct WaveSpawned {
+    enemies: u32,
+    boss: bool
+}
+
+impl Wave {
+    // every fifth wave ends with a boss
+    fn has_boss(&self) -> bool {
+        self.0 % BOSS_WAVE_INTERVAL == 0
+    }
+
+    fn max_enemies(&self, base: u32) -> u32 {
+        base + (self.0 - 1) / 2
+    }
+
+    fn formation_members(&self) -> u32 {
+        (MIN_FORMATION_MEMBERS + (self.0 - 1) / 2).min(MAX_FORMATION_MEMBERS)
+    }
+
+    fn budget(&self) -> u32 {
+        WAVE_BASE_BUDGET + WAVE_BUDGET_STEP * (self.0 - 1)
+    }
+
+    fn speed_scale(&self) -> f32 {
+        (1. + 0.08 * (self.0 - 1) as f32).min(2.)
+    }
+
+    // lasers pick up less than the ships do, or late waves turn undodgeable
+    fn laser_speed_scale(&self) -> f32 {
+        (1. + 0.04 * (self.0 - 1) as f32).min(1.6)
+    }
+
+    fn fire_interval(&self) -> f64 {
+        (ENEMY_FIRE_INTERVAL * 0.92_f64.powi(self.0 as i32 - 1)).max(0.3)
+    }
+}
 
 pub struct Enemy;
+// a destroyed splitter, turned into its two children next frame
+pub struct SplitToSpawn(pub Vec3);
 pub struct FromEnemy;
+// normalized travel direction of an enemy laser
+pub struct EnemyLaserDir(pub Vec2);
 pub struct EnemyPlugin;
+// telegraphs an enemy about to enter at `position`
+pub struct SpawnWarning {
+    pub position: Vec3,
+    pub timer: Timer
+}
+pub struct Health {
+    pub current: u32,
+    pub max: u32
+}
+
+impl Health {
+    pub fn new(max: u32) -> Self {
+        Self { current: max, max }
+    }
+}
+
+impl Default for Health {
+    fn default() -> Self {
+        Self::new(1)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EnemyKind {
+    Grunt,
+    Diver,
+    Tank,
+    // breaks into two grunts when destroyed
+    Splitter,
+    // speeds up as it takes damage
+    Berserker,
+    // shields the enemies around it, never fires
+    Guardian
+}
 
-#[derive(Default, Clone)]
+// how an enemy's volley is laid out
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum EnemyFirePattern {
+    Straight,
+    // one laser at the player
+    Aimed,
+    // three lasers fanned out around straight down
+    Spread
+}
+
+impl EnemyKind {
+    fn random(rng: &mut impl Rng) -> Self {
+        match rng.gen_range(0..100) {
+            0..=49 => EnemyKind::Grunt,
+            50..=74 => EnemyKind::Diver,
+            75..=87 => EnemyKind::Tank,
+            88..=93 => EnemyKind::Splitter,
+            94..=96 => EnemyKind::Berserker,
+            _ => EnemyKind::Guardian
+        }
+    }
+
+    fn health(&self) -> Health {
+        match self {
+            EnemyKind::Grunt | EnemyKind::Diver => Health::new(1),
+            EnemyKind::Splitter => Health::new(2),
+            EnemyKind::Tank => Health::new(3),
+            EnemyKind::Berserker => Health::new(4),
+            EnemyKind::Guardian => Health::new(3)
+        }
+    }
+
+    // relative to the configured base enemy speed
+    fn speed(&self, base: f32) -> Speed {
+        match self {
+            EnemyKind::Grunt => Speed(base),
+            EnemyKind::Diver => Speed(base * 1.3),
+            EnemyKind::Tank => Speed(base * 0.7),
+            EnemyKind::Splitter => Speed(base * 0.9),
+            EnemyKind::Berserker => Speed(base * 0.8),
+            EnemyKind::Guardian => Speed(base * 0.6)
+        }
+    }
+
+    // divers only swoop around and guardians are busy shielding, they never shoot
+    fn fire_pattern(&self) -> Option<EnemyFirePattern> {
+        match self {
+            EnemyKind::Grunt => Some(EnemyFirePattern::Straight),
+            EnemyKind::Diver | EnemyKind::Guardian => None,
+            EnemyKind::Tank => Some(EnemyFirePattern::Aimed),
+            EnemyKind::Splitter => Some(EnemyFirePattern::Spread),
+            EnemyKind::Berserker => Some(EnemyFirePattern::Straight)
+        }
+    }
+
+    // its frame on the enemy sheet, splitters and berserkers are tinted copies
+    fn sprite(&self) -> TextureAtlasSprite {
+        let (index, color) = match self {
+            EnemyKind::Grunt => (GRUNT_FRAME, Color::WHITE),
+            EnemyKind::Diver => (DIVER_FRAME, Color::WHITE),
+            EnemyKind::Tank => (TANK_FRAME, Color::WHITE),
+            EnemyKind::Splitter => (GRUNT_FRAME, Color::rgb(0.5, 1., 0.5)),
+            EnemyKind::Berserker => (DIVER_FRAME, Color::rgb(1., 0.4, 0.4)),
+            EnemyKind::Guardian => (TANK_FRAME, Color::rgb(0.5, 0.8, 1.))
+        };
+        TextureAtlasSprite { index, color, ..Default::default() }
+    }
+}
+
+// within reach of a guardian, put on and taken off by guardian_shield every frame
+pub struct Shielded;
+// drawn over the shielded enemy it names
+struct GuardianBubble(Entity);
+
+// a berserker's speed at full health
+pub struct Berserk {
+    base_speed: f32
+}
+
+impl Berserk {
+    // recomputed whenever it's hit, movement just reads the Speed
+    pub fn speed(&self, health: &Health) -> Speed {
+        let hurt = 1. - health.current as f32 / health.max as f32;
+        Speed(self.base_speed * (1. + BERSERK_RAGE * hurt).min(BERSERK_MAX_SPEED))
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum MovementPhase {
+    Entering,
+    Formation,
+    // heading for where the player was when the dive started
+    Diving(Vec2)
+}
+
+#[derive(Debug, Default, Clone, PartialEq)]
 struct Formation {
     start: (f32, f32),
-    radius: (f32, f32),
+    // where the middle of the group holds
     offset: (f32, f32),
-    angle: f32,
-    group_id: u32
+    group_id: u32,
+    // how many the group was made for, fixed when it starts even if the wave moves on
+    size: u32,
+    // position within the group, 0 is the first to arrive
+    slot: u32
+}
+
+impl Formation {
+    // slots are laid out in a row centered on the group's offset
+    fn slot_x(&self) -> f32 {
+        (self.slot as f32 - (self.size as f32 - 1.) / 2.) * FORMATION_SLOT_SPACING
+    }
+}
+
+// the sway phase of every group that still has members, keyed by group_id
+#[derive(Default)]
+struct FormationGroup(HashMap<u32, f32>);
+
+impl FormationGroup {
+    fn sway(&self, group_id: u32) -> f32 {
+        self.0.get(&group_id).map_or(0., |phase| FORMATION_SWAY * phase.sin())
+    }
 }
 
 #[derive(Default)]
@@ -30,34 +260,37 @@ struct FormationMaker {
 }
 
 impl FormationMaker {
-    fn make(&mut self, window_size: &WindowSize) -> Formation {
-        match (&self.current_formation, self.current_formation_members >= MAX_FORMATION_MEMBERS) {
+    fn make(&mut self, wave: &Wave, window_size: &WindowSize, rng: &mut impl Rng) -> Formation {
+        let full = self.current_formation.as_ref().map_or(true, |formation| self.current_formation_members >= formation.size);
+        match (&self.current_formation, full) {
             // if first formation or previous formation null
             (None, _) | (_, true) => {
                 // compute the start x/y
-                let mut rng = thread_rng();
-                let (h_span, w_span) = (window_size.height / 2. - 100., window_size.width / 4.);
-                let x = if rng.gen::<bool>() { window_size.width } else { window_size.height };
-                let y = rng.gen_range(-h_span..h_span) as f32;
+                // keep the ranges non-empty however small the window gets
+                let h_span = (window_size.height / 2. - 100.).max(MIN_SPAN);
+                let w_span = (window_size.width / 4.).max(MIN_SPAN);
+                // enter from either the left or the right edge
+                // fly in diagonally from one of the top corners
+                let x = if rng.gen::<bool>() { window_size.width / 2. } else { -window_size.width / 2. };
+                let y = window_size.height / 2. + 50.;
                 let start = (x, y);
 
-                // compute offset and radius
+                // compute the offset the group holds at
                 let offset = (rng.gen_range(-w_span..w_span), rng.gen_range(0.0..h_span));
-                let radius = (rng.gen_range(80.0..150.0), 100.);
-                let angle: f32 = (y - offset.0).atan2(x - offset.1);
 
                 // create new formation
                 self.group_seq += 1;
                 let group_id = self.group_seq;
-                let formation = Formation { start, offset, radius, angle, group_id };
+                let formation = Formation { start, offset, group_id, size: wave.formation_members(), slot: 0 };
                 self.current_formation = Some(formation.clone());
                 self.current_formation_members = 1;
                 formation
             }
             // if still within the formation count
             (Some(formation), false) => {
+                let formation = Formation { slot: self.current_formation_members, ..formation.clone() };
                 self.current_formation_members += 1;
-                formation.clone()
+                formation
             }
         }
     }
@@ -68,17 +301,44 @@ impl Plugin for EnemyPlugin {
     fn build(&self, app: &mut AppBuilder) {
        app
            .insert_resource(FormationMaker::default())
-           .add_system(enemy_laser_movment.system())
-           .add_system(enemy_movment.system())
+           .insert_resource(FormationGroup::default())
+           .insert_resource(WaveSpawned::default())
+           .insert_resource(EnemyFireTimer(Timer::from_seconds(ENEMY_FIRE_INTERVAL as f32, true)))
+           .add_system_set(
+               SystemSet::on_enter(AppState::InGame)
+               .with_system(reset_formations.system())
+           )
+           .add_system_set(
+               SystemSet::on_update(AppState::InGame)
+               .with_system(enemy_laser_movment.system())
+               .with_system(enemy_movment.system())
+               .with_system(sway_formation_groups.system())
+               .with_system(enemy_fire.system())
+               .with_system(check_wave_cleared.system())
+               .with_system(spawn_warning.system())
+               .with_system(split_to_spawn.system())
+               .with_system(guardian_shield.system())
+               .with_system(guardian_bubble.system())
+           )
            .add_system_set(
                     SystemSet::new()
-                    .with_run_criteria(FixedTimestep::step(1.0))
+                    .with_run_criteria(FixedTimestep::step(1.0).chain(in_game_or_menu.system()))
                     .with_system(enemy_spawn.system())
-            ).add_system_set(
-                SystemSet::new()
-                .with_run_criteria(FixedTimestep::step(0.9))
-                .with_system(enemy_fire.system())
-            );
+                    .with_system(practice_spawn.system())
+            )
+           // the title screen flies a few formations in the background, shooting at the demo ship
+           .add_system_set(
+               SystemSet::on_update(AppState::MainMenu)
+               .with_system(enemy_laser_movment.system())
+               .with_system(enemy_movment.system())
+               .with_system(enemy_fire.system())
+               .with_system(sway_formation_groups.system())
+               .with_system(spawn_warning.system())
+           )
+           .add_system_set(
+               SystemSet::on_exit(AppState::MainMenu)
+               .with_system(clear_demo_enemies.system())
+           );
     }
 }
 
@@ -87,104 +347,708 @@ fn enemy_spawn(
     mut commands: Commands,
     mut active_enemies: ResMut<ActiveEnemies>,
     mut formation_maker: ResMut<FormationMaker>,
+    mut wave_spawned: ResMut<WaveSpawned>,
+    mut rng: ResMut<GameRng>,
+    wave: Res<Wave>,
+    difficulty: Res<Difficulty>,
     materials: Res<Materials>,
-    window_size: Res<WindowSize>
+    config: Res<GameConfig>,
+    window_size: Res<WindowSize>,
+    game_mode: Res<GameMode>,
+    app_state: Res<State<AppState>>,
+    paused: Res<Paused>
 ) {
-    if active_enemies.0 < MAX_ENEMIES {
-        let formation = formation_maker.make(&window_size);
+    // the other modes bring their own enemies, the title screen demo keeps flying either way
+    if !game_mode.has_waves() && *app_state.current() == AppState::InGame {
+        return;
+    }
+    let max_enemies = difficulty.max_enemies(wave.max_enemies(config.max_enemies));
+    // nothing clears waves on the title screen, so the demo isn't held to a budget
+    let budget_left = *app_state.current() == AppState::MainMenu || wave_spawned.enemies < wave.budget();
+    if !paused.0 && active_enemies.0 < max_enemies && budget_left {
+        let formation = formation_maker.make(&wave, &window_size, &mut rng.rng);
         let (x, y) = formation.start;
+        let kind = EnemyKind::random(&mut rng.rng);
+        let position = Vec3::new(x, y, 10.);
+        // the arrow sits just inside the corner, pointing where the enemy will fly
+        let heading = Vec2::new(formation.offset.0 - x, formation.offset.1 - y).normalize_or_zero();
+        let arrow_x = x.signum() * (window_size.width / 2. - SPAWN_WARNING_INSET);
+        let arrow_y = window_size.height / 2. - SPAWN_WARNING_INSET;
         commands.spawn_bundle(SpriteBundle {
-            material: materials.enemy.clone(),
+            material: materials.spawn_warning.clone(),
             transform: Transform {
-                translation: Vec3::new(x, y, 10.),
-                scale: Vec3::new(SCALE, SCALE, 1.),
+                translation: Vec3::new(arrow_x, arrow_y, 50.),
+                rotation: facing(heading),
                 ..Default::default()
             },
             ..Default::default()
         })
-        .insert(Speed::default())
-        .insert(Enemy)
+        .insert(SpawnWarning { position, timer: Timer::from_seconds(SPAWN_WARNING_TIME, false) })
+        .insert(Speed(kind.speed(config.enemy_speed).0 * wave.speed_scale() * difficulty.speed_scale()))
+        .insert(kind)
         .insert(formation);
 
+        // the slot is taken as soon as the warning shows
         active_enemies.0 += 1;
+        wave_spawned.enemies += 1;
     }
 }
 
+fn spawn_warning(
+    mut commands: Commands,
+    time: Res<Time>,
+    time_scale: Res<TimeScale>,
+    paused: Res<Paused>,
+    materials: Res<Materials>,
+    mut query: Query<(Entity, &mut SpawnWarning, &mut Visible, &Speed, &EnemyKind, &Formation)>
+) {
+    if paused.0 {
+        return;
+    }
+    for (entity, mut warning, mut visible, speed, kind, formation) in query.iter_mut() {
+        warning.timer.tick(time.delta().mul_f32(time_scale.0));
+        if warning.timer.finished() {
+            let mut enemy = commands.spawn_bundle(SpriteSheetBundle {
+                texture_atlas: materials.enemies.clone(),
+                sprite: kind.sprite(),
+                transform: Transform {
+                    translation: warning.position,
+                    scale: Vec3::new(SCALE, SCALE, 1.),
+                    ..Default::default()
+                },
+                ..Default::default()
+            });
+            enemy
+                .insert(Speed(speed.0))
+                .insert(Enemy)
+                .insert(kind.health())
+                .insert(*kind)
+                .insert(formation.clone())
+                .insert(MovementPhase::Entering);
+            if *kind == EnemyKind::Berserker {
+                enemy.insert(Berserk { base_speed: speed.0 });
+            }
+            commands.entity(entity).despawn();
+            continue;
+        }
+        visible.is_visible = (warning.timer.elapsed_secs() / SPAWN_WARNING_BLINK) as u32 % 2 == 0;
+    }
+}
 
-fn enemy_fire(
+// the two grunts peel off in opposite directions and dive off screen. They're
+// on top of the wave budget and may briefly push ActiveEnemies past the cap,
+// which only holds back regular spawns until they're gone.
+fn split_to_spawn(
+    mut commands: Commands,
+    mut active_enemies: ResMut<ActiveEnemies>,
+    materials: Res<Materials>,
+    config: Res<GameConfig>,
+    wave: Res<Wave>,
+    difficulty: Res<Difficulty>,
+    query: Query<(Entity, &SplitToSpawn)>
+) {
+    for (entity, split) in query.iter() {
+        let kind = EnemyKind::Grunt;
+        for &side in &[-1., 1.] {
+            let dir = Vec2::new(side * 0.6, -0.8);
+            commands.spawn_bundle(SpriteSheetBundle {
+                texture_atlas: materials.enemies.clone(),
+                sprite: kind.sprite(),
+                transform: Transform {
+                    translation: split.0 + Vec3::new(side * SPLIT_OFFSET, 0., 0.),
+                    rotation: facing(dir),
+                    scale: Vec3::new(SPLIT_SCALE, SPLIT_SCALE, 1.)
+                },
+                ..Default::default()
+            })
+            .insert(Speed(kind.speed(config.enemy_speed).0 * wave.speed_scale() * difficulty.speed_scale()))
+            .insert(Enemy)
+            .insert(kind.health())
+            .insert(kind)
+            // grunts don't split again, so this stops after one generation
+            .insert(Formation::default())
+            .insert(MovementPhase::Diving(dir));
+            active_enemies.0 += 1;
+        }
+        commands.entity(entity).despawn();
+    }
+}
+
+// a half-filled formation from the demo or the last game would skew the seeded pattern
+fn reset_formations(mut formation_maker: ResMut<FormationMaker>, continuing: Res<Continuing>) {
+    if continuing.0 {
+        return;
+    }
+    *formation_maker = FormationMaker::default();
+}
+
+// a fresh grid whenever the last one is cleared. Without a Formation these
+// are left alone by enemy_movment, and enemy_fire stands down in this mode.
+fn practice_spawn(
+    mut commands: Commands,
+    mut active_enemies: ResMut<ActiveEnemies>,
+    materials: Res<Materials>,
+    window_size: Res<WindowSize>,
+    game_mode: Res<GameMode>,
+    app_state: Res<State<AppState>>,
+    paused: Res<Paused>
+) {
+    if *game_mode != GameMode::Practice || *app_state.current() != AppState::InGame || paused.0 || active_enemies.0 > 0 {
+        return;
+    }
+    // a row of each kind, the sturdy tanks at the back
+    let rows = [EnemyKind::Tank, EnemyKind::Diver, EnemyKind::Grunt];
+    let top = window_size.height / 2. - 100.;
+    for (row, kind) in rows.iter().enumerate() {
+        for column in 0..PRACTICE_GRID {
+            let x = (column as f32 - (PRACTICE_GRID - 1) as f32 / 2.) * PRACTICE_SPACING;
+            let y = top - row as f32 * PRACTICE_SPACING * 0.7;
+            commands.spawn_bundle(SpriteSheetBundle {
+                texture_atlas: materials.enemies.clone(),
+                sprite: kind.sprite(),
+                transform: Transform {
+                    translation: Vec3::new(x, y, 10.),
+                    scale: Vec3::new(SCALE, SCALE, 1.),
+                    ..Default::default()
+                },
+                ..Default::default()
+            })
+            .insert(Enemy)
+            .insert(kind.health())
+            .insert(*kind);
+            active_enemies.0 += 1;
+        }
+    }
+}
+
+// guardians don't cover each other, or a pair of them could never be shot down
+fn guardian_shield(
+    mut commands: Commands,
+    query: Query<(Entity, &Transform, &EnemyKind, Option<&Shielded>), With<Enemy>>
+) {
+    let guardians: Vec<Vec3> = query
+        .iter()
+        .filter(|(_, _, &kind, _)| kind == EnemyKind::Guardian)
+        .map(|(_, tf, ..)| tf.translation)
+        .collect();
+    for (entity, tf, &kind, shielded) in query.iter() {
+        let covered = kind != EnemyKind::Guardian
+            && guardians.iter().any(|guardian| guardian.truncate().distance(tf.translation.truncate()) <= GUARDIAN_RADIUS);
+        match (covered, shielded.is_some()) {
+            (true, false) => {
+                commands.entity(entity).insert(Shielded);
+            }
+            (false, true) => {
+                commands.entity(entity).remove::<Shielded>();
+            }
+            _ => {}
+        }
+    }
+}
+
+// keeps a bubble over each shielded enemy, like the player's shield
+fn guardian_bubble(
     mut commands: Commands,
     materials: Res<Materials>,
-    enemy_quert: Query<&Transform, With<Enemy>>
+    enemy_query: Query<(Entity, &Transform), (With<Enemy>, With<Shielded>)>,
+    mut bubble_query: Query<(Entity, &mut Transform, &GuardianBubble), Without<Enemy>>
 ) {
-   for &tf in enemy_quert.iter() {
-        let (x, y) = (tf.translation.x, tf.translation.y);
+    let mut covered = HashSet::new();
+    for (bubble_entity, mut bubble_tf, bubble) in bubble_query.iter_mut() {
+        match enemy_query.get(bubble.0) {
+            Ok((_, enemy_tf)) => {
+                bubble_tf.translation.x = enemy_tf.translation.x;
+                bubble_tf.translation.y = enemy_tf.translation.y;
+                covered.insert(bubble.0);
+            }
+            // the guardian moved off or went down, or the enemy is gone
+            Err(_) => commands.entity(bubble_entity).despawn()
+        }
+    }
+    for (enemy_entity, enemy_tf) in enemy_query.iter().filter(|(entity, _)| !covered.contains(entity)) {
         commands
-            .spawn_bundle(
-                SpriteBundle {
-                    material: materials.enemy_laser.clone(),
-                    transform: Transform {
-                        translation: Vec3::new(x, y - 15., 0.),
-                        scale: Vec3::new(SCALE, -SCALE, 1.),
-                        ..Default::default()
-                    },
+            .spawn_bundle(SpriteBundle {
+                material: materials.shield.clone(),
+                transform: Transform {
+                    translation: Vec3::new(enemy_tf.translation.x, enemy_tf.translation.y, 11.),
+                    scale: Vec3::new(GUARDIAN_BUBBLE_SCALE, GUARDIAN_BUBBLE_SCALE, 1.),
                     ..Default::default()
-                }
-            )
-            .insert(Laser)
-            .insert(FromEnemy)
-            .insert(Speed::default());
+                },
+                ..Default::default()
+            })
+            .insert(GuardianBubble(enemy_entity));
+    }
+}
+
+fn clear_demo_enemies(
+    mut commands: Commands,
+    mut active_enemies: ResMut<ActiveEnemies>,
+    mut wave_spawned: ResMut<WaveSpawned>,
+    query: Query<Entity, Or<(With<Enemy>, With<SpawnWarning>, With<Laser>)>>
+) {
+    // the demo ship's lasers and the ones fired at it go too
+    for entity in query.iter() {
+        commands.entity(entity).despawn();
+    }
+    active_enemies.0 = 0;
+    *wave_spawned = WaveSpawned::default();
+}
+
+fn check_wave_cleared(
+    mut commands: Commands,
+    mut active_enemies: ResMut<ActiveEnemies>,
+    mut wave: ResMut<Wave>,
+    mut wave_spawned: ResMut<WaveSpawned>,
+    mut run_stats: ResMut<RunStats>,
+    game_mode: Res<GameMode>
+) {
+    if !game_mode.has_waves() {
+        return;
+    }
+    if active_enemies.0 == 0 && wave_spawned.enemies >= wave.budget() {
+        // a boss nobody can shoot would hold the wave open forever
+        if wave.has_boss() && !wave_spawned.boss && game_mode.can_fire() {
+            // the boss holds the wave open until it's destroyed
+            commands.spawn().insert(BossToSpawn { level: 0 });
+            active_enemies.0 += 1;
+            wave_spawned.boss = true;
+        } else {
+            wave.0 += 1;
+            run_stats.waves_cleared += 1;
+            wave_spawned.enemies = 0;
+            wave_spawned.boss = false;
+        }
+    }
+}
+
+
+fn enemy_fire(
+    mut commands: Commands,
+    materials: Res<Materials>,
+    config: Res<GameConfig>,
+    paused: Res<Paused>,
+    time: Res<Time>,
+    time_scale: Res<TimeScale>,
+    wave: Res<Wave>,
+    difficulty: Res<Difficulty>,
+    colorblind: Res<ColorblindMode>,
+    game_mode: Res<GameMode>,
+    app_state: Res<State<AppState>>,
+    mut fire_timer: ResMut<EnemyFireTimer>,
+    mut rng: ResMut<GameRng>,
+    enemy_quert: Query<(&Transform, &EnemyKind), With<Enemy>>,
+    player_query: Query<&Transform, With<Player>>
+) {
+   // practice targets hold fire, the title screen demo still shoots whatever mode is picked
+   if paused.0 || (*game_mode == GameMode::Practice && *app_state.current() == AppState::InGame) {
+       return;
+   }
+   // waves and difficulty can change the cadence mid-game
+   let interval = Duration::from_secs_f64(wave.fire_interval() * difficulty.fire_scale());
+   if fire_timer.0.duration() != interval {
+       fire_timer.0.set_duration(interval);
+   }
+   if !fire_timer.0.tick(time.delta().mul_f32(time_scale.0)).just_finished() {
+       return;
+   }
+   let players: Vec<Vec3> = player_query.iter().map(|tf| tf.translation).collect();
+   // in co-op each volley is lined up on one of the ships at random
+   let target = players.choose(&mut rng.rng).copied();
+   let mut shooters: Vec<(Transform, EnemyFirePattern)> = enemy_quert
+        .iter()
+        .filter_map(|(&tf, kind)| kind.fire_pattern().map(|pattern| (tf, pattern)))
+        .collect();
+   // the enemy lined up with the player always gets a shot, the rest are picked at random
+   shooters.shuffle(&mut rng.rng);
+   if let Some(target) = target {
+        let player_x = target.x;
+        if let Some(nearest) = (0..shooters.len()).min_by(|&a, &b| {
+            let da = (shooters[a].0.translation.x - player_x).abs();
+            let db = (shooters[b].0.translation.x - player_x).abs();
+            da.partial_cmp(&db).unwrap()
+        }) {
+            shooters.swap(0, nearest);
+        }
+   }
+   let laser_speed = config.enemy_laser_speed * wave.laser_speed_scale();
+   for (tf, pattern) in shooters.into_iter().take(MAX_SHOOTERS) {
+        let muzzle = Vec3::new(tf.translation.x, tf.translation.y - 15., 0.);
+        let straight_down = Vec2::new(0., -1.);
+        let dirs = match (pattern, nearest_player(&players, muzzle)) {
+            (EnemyFirePattern::Aimed, Some(player)) => {
+                let to_player = (player - muzzle).truncate();
+                vec![if to_player.length() > 0. { to_player.normalize() } else { straight_down }]
+            }
+            (EnemyFirePattern::Spread, _) => {
+                [-ENEMY_SPREAD_ANGLE, 0., ENEMY_SPREAD_ANGLE]
+                    .iter()
+                    .map(|angle| Vec2::new(angle.sin(), -angle.cos()))
+                    .collect()
+            }
+            // with no player to aim at, aimed shots go straight down too
+            _ => vec![straight_down]
+        };
+        for dir in dirs {
+            spawn_enemy_laser(&mut commands, materials.enemy_laser(&colorblind), muzzle, dir, laser_speed);
+        }
+        spawn_muzzle_flash(&mut commands, &materials, muzzle);
    } 
 }
 
+pub fn spawn_enemy_laser(commands: &mut Commands, material: Handle<ColorMaterial>, translation: Vec3, dir: Vec2, speed: f32) {
+    commands
+        .spawn_bundle(
+            SpriteBundle {
+                material,
+                transform: Transform {
+                    translation,
+                    // the sprite points down, turn it toward the direction of travel
+                    rotation: Quat::from_rotation_z(dir.x.atan2(-dir.y)),
+                    scale: Vec3::new(SCALE, -SCALE, 1.)
+                },
+                ..Default::default()
+            }
+        )
+        .insert(Laser)
+        .insert(FromEnemy)
+        .insert(EnemyLaserDir(dir))
+        .insert(Speed(speed));
+}
+
 fn enemy_laser_movment(
+    time: Res<Time>,
+    time_scale: Res<TimeScale>,
+    paused: Res<Paused>,
+    mut laser_query: Query<(&Speed, &EnemyLaserDir, &mut Transform), (With<Laser>, With<FromEnemy>)>
+) {
+    if paused.0 {
+        return;
+    }
+    for (speed, dir, mut tf) in laser_query.iter_mut() {
+        let movement = dir.0 * speed.0 * time.delta_seconds() * time_scale.0;
+        tf.translation += movement.extend(0.);
+    }
+}
+
+
+fn nearest_player(players: &[Vec3], from: Vec3) -> Option<Vec3> {
+    players
+        .iter()
+        .copied()
+        .min_by(|a, b| a.distance_squared(from).partial_cmp(&b.distance_squared(from)).unwrap())
+}
+
+// enemy sprites point down, turn them toward the direction of travel
+fn facing(dir: Vec2) -> Quat {
+    Quat::from_rotation_z(dir.x.atan2(-dir.y))
+}
+
+// every group sweeps side to side on its own phase, members follow in enemy_movment
+fn sway_formation_groups(
+    time: Res<Time>,
+    time_scale: Res<TimeScale>,
+    paused: Res<Paused>,
+    mut groups: ResMut<FormationGroup>,
+    query: Query<&Formation, With<Enemy>>
+) {
+    if paused.0 {
+        return;
+    }
+    let alive: HashSet<u32> = query.iter().map(|formation| formation.group_id).collect();
+    groups.0.retain(|group_id, _| alive.contains(group_id));
+    let step = FORMATION_SWAY_RATE * time.delta_seconds() * time_scale.0;
+    for group_id in alive {
+        *groups.0.entry(group_id).or_insert(0.) += step;
+    }
+}
+
+fn enemy_movment(
     mut commands: Commands,
+    time: Res<Time>,
+    time_scale: Res<TimeScale>,
+    paused: Res<Paused>,
     window_size: Res<WindowSize>,
-    mut laser_query: Query<(Entity, &Speed, &mut Transform), (With<Laser>, With<FromEnemy>)>
+    groups: Res<FormationGroup>,
+    mut active_enemies: ResMut<ActiveEnemies>,
+    mut rng: ResMut<GameRng>,
+    player_query: Query<&Transform, With<Player>>,
+    mut query: Query<(Entity, &mut Transform, &Speed, &Formation, &mut MovementPhase), (With<Enemy>, Without<Player>)>
 ) {
-    for (entity, speed, mut tf) in laser_query.iter_mut() {
-        tf.translation.y -= speed.0 * TIME_PER_FRAME;
-        if tf.translation.y < -window_size.height / 2. - 50. {
-            commands.entity(entity).despawn();
+    if paused.0 {
+        return;
+    }
+    let delta = time.delta_seconds() * time_scale.0;
+    let players: Vec<Vec3> = player_query.iter().map(|tf| tf.translation).collect();
+    for (entity, mut tf, speed, formation, mut phase) in query.iter_mut() {
+        let max_distance = delta * speed.0;
+        let (x_org, y_org) = (tf.translation.x, tf.translation.y);
+
+        if let MovementPhase::Diving(dir) = *phase {
+            tf.translation.x += dir.x * max_distance;
+            tf.translation.y += dir.y * max_distance;
+            tf.rotation = facing(dir);
+            // gone off the bottom, free the slot for the next spawn
+            if tf.translation.y < -window_size.height / 2. - 50. {
+                commands.entity(entity).despawn();
+                active_enemies.0 = active_enemies.0.saturating_sub(1);
+            }
+            continue;
+        }
+
+        if *phase == MovementPhase::Formation && rng.rng.gen_bool((DIVE_CHANCE * delta as f64).min(1.)) {
+            let target = match nearest_player(&players, tf.translation) {
+                Some(player) => player.truncate(),
+                None => Vec2::new(x_org, -window_size.height / 2.)
+            };
+            let to_target = target - Vec2::new(x_org, y_org);
+            // always dive downward so the enemy is guaranteed to leave the screen
+            let dir = if to_target.y < 0. { to_target.normalize() } else { Vec2::new(0., -1.) };
+            *phase = MovementPhase::Diving(dir);
+            continue;
         }
+
+        // the member's slot, carried along by the group's sweep
+        let (x_offset, y_offset) = formation.offset;
+        let destination = Vec2::new(x_offset + formation.slot_x() + groups.sway(formation.group_id), y_offset);
+        let to_destination = destination - Vec2::new(x_org, y_org);
+        let distance = to_destination.length();
+
+        // make sure to not overshoot
+        if distance <= max_distance {
+            tf.translation.x = destination.x;
+            tf.translation.y = destination.y;
+            // reached the slot, from here on it's holding formation
+            if *phase == MovementPhase::Entering {
+                *phase = MovementPhase::Formation;
+            }
+        } else {
+            let step = to_destination / distance * max_distance;
+            tf.translation.x += step.x;
+            tf.translation.y += step.y;
+        }
+
+        tf.rotation = match *phase {
+            // face the player while holding, the sweep shouldn't swing the sprite around
+            MovementPhase::Formation => facing(Vec2::new(0., -1.)),
+            // skip the tiny steps, they'd only make the sprite jitter
+            _ if distance > 0.01 => facing(to_destination / distance),
+            _ => tf.rotation
+        };
     }
 }
 
+#[cfg(test)]
+mod tests {
+    use bevy::app::Events;
+    use rand::{SeedableRng, rngs::StdRng};
 
-fn enemy_movment(mut query: Query<(&mut Transform, &Speed, &mut Formation), With<Enemy>>) {
-    for (mut tf, speed, mut formation) in query.iter_mut() {
-        let max_distance = TIME_PER_FRAME * speed.0;
-        let (x_org, y_org) = (tf.translation.x, tf.translation.y);
-        
-        // Get the ellipse
-        let (x_offset, y_offset) = formation.offset;
-        let (x_radius, y_radius) = formation.radius;
+    use super::*;
+    use crate::{EnemyKilled, damage_enemy};
 
-        // Compute the destination
-        let dir = if formation.start.0 > 0. { 1. } else { -1. };
-        let angle = formation.angle + dir * speed.0 * TIME_PER_FRAME / (x_radius.min(y_radius) * PI / 2.);
-            
-        // Calculate the destination
-        let x_dst = x_radius * angle.cos() + x_offset;
-        let y_dst = y_radius * angle.sin() + y_offset;
+    const WINDOW: WindowSize = WindowSize { width: 598., height: 676. };
 
-        // Calculate the distance
-        let (delta_x, delta_y) = (x_org - x_dst, y_org - y_dst);
-        let distance = (delta_x * delta_x + delta_y * delta_y).sqrt();
+    fn hit_all(
+        mut commands: Commands,
+        mut active_enemies: ResMut<ActiveEnemies>,
+        mut enemy_killed: EventWriter<EnemyKilled>,
+        mut enemy_query: Query<(Entity, &Transform, &mut Health)>,
+        mut berserk_query: Query<(&Berserk, &mut Speed)>
+    ) {
+        for (entity, tf, mut health) in enemy_query.iter_mut() {
+            damage_enemy(&mut commands, &mut active_enemies, &mut enemy_killed, &mut berserk_query, entity, tf, &mut health, None, None, 1);
+        }
+    }
 
-        let distance_ratio = if distance == 0. { 0. } else { max_distance / distance };
-        
-        // Calculate the final x/y (make sure to not overshoot)
-        let x = x_org - delta_x * distance_ratio;
-        let y = y_org - delta_y * distance_ratio;
+    #[test]
+    fn berserker_speeds_up_after_a_hit() {
+        let mut world = World::default();
+        world.insert_resource(ActiveEnemies(1));
+        world.insert_resource(Events::<EnemyKilled>::default());
+        let health = EnemyKind::Berserker.health().max;
+        let berserker = world
+            .spawn()
+            .insert_bundle((Transform::default(), EnemyKind::Berserker.health(), Berserk { base_speed: 100. }, Speed(100.)))
+            .id();
 
-        if distance < max_distance * speed.0 / 20. {
-            formation.angle = angle;
+        SystemStage::single(hit_all.system()).run(&mut world);
+
+        assert_eq!(world.get::<Health>(berserker).unwrap().current, health - 1);
+        assert!(world.get::<Speed>(berserker).unwrap().0 > 100.);
+    }
+
+    #[test]
+    fn make_survives_a_tiny_window() {
+        let mut maker = FormationMaker::default();
+        let mut rng = StdRng::seed_from_u64(0);
+        let window = WindowSize { width: 600., height: 100. };
+        for _ in 0..100 {
+            let formation = maker.make(&Wave(1), &window, &mut rng);
+            assert!(formation.offset.1 >= 0.);
         }
+    }
 
-        tf.translation.x = if delta_x > 0. { x.max(x_dst) } else { x.min(x_dst) };
-        tf.translation.y = if delta_y > 0. { y.max(y_dst) } else { y.min(y_dst) };
+    #[test]
+    fn formations_enter_from_both_edges() {
+        let mut maker = FormationMaker::default();
+        let mut rng = StdRng::seed_from_u64(0);
+        let xs: Vec<f32> = (0..100).map(|_| maker.make(&Wave(1), &WINDOW, &mut rng).start.0).collect();
+        assert!(xs.iter().all(|x| x.abs() == WINDOW.width / 2.));
+        assert!(xs.iter().any(|&x| x < 0.) && xs.iter().any(|&x| x > 0.));
     }
 
-}
+    #[test]
+    fn entry_heading_points_at_the_slot() {
+        // the last of three slots sits one spacing right of the group's offset
+        let formation = Formation { start: (300., 388.), offset: (0., 100.), group_id: 1, size: 3, slot: 2 };
+        assert_eq!(formation.slot_x(), FORMATION_SLOT_SPACING);
 
+        // from (300, 388) to (60, 100) is 240 left and 288 down, atan2(-240, 288) by hand
+        let to_slot = Vec2::new(formation.offset.0 + formation.slot_x(), formation.offset.1) - Vec2::from(formation.start);
+        assert!(facing(to_slot.normalize()).abs_diff_eq(Quat::from_rotation_z(-0.694_738), 1e-5));
+    }
 
+    // a headless game that only spawns, each update queues at most one enemy
+    fn spawning_app(seed: u64) -> App {
+        let mut app = App::build();
+        app.add_plugins(MinimalPlugins)
+            .insert_resource(GameRng::new(Some(seed)))
+            .insert_resource(State::new(AppState::InGame))
+            .insert_resource(GameConfig { max_enemies: 10, ..Default::default() })
+            .insert_resource(Materials::default())
+            .insert_resource(WINDOW)
+            .insert_resource(Wave(1))
+            .insert_resource(Difficulty::Normal)
+            .insert_resource(GameMode::Normal)
+            .insert_resource(Paused(false))
+            .insert_resource(ActiveEnemies(0))
+            .init_resource::<WaveSpawned>()
+            .init_resource::<FormationMaker>()
+            .add_system(enemy_spawn.system());
+        app.app
+    }
+
+    fn first_formations(seed: u64) -> Vec<(Formation, EnemyKind)> {
+        let mut app = spawning_app(seed);
+        for _ in 0..5 {
+            app.update();
+        }
+        let mut spawned: Vec<(Formation, EnemyKind)> = app.world
+            .query::<(&Formation, &EnemyKind)>()
+            .iter(&app.world)
+            .map(|(formation, &kind)| (formation.clone(), kind))
+            .collect();
+        spawned.sort_by_key(|(formation, _)| (formation.group_id, formation.slot));
+        spawned
+    }
+
+    #[test]
+    fn same_seed_spawns_the_same_formations() {
+        let first = first_formations(42);
+        assert_eq!(first.len(), 5);
+        assert_eq!(first, first_formations(42));
+        assert_ne!(first, first_formations(43));
+    }
+
+    #[test]
+    fn formations_grow_with_the_wave() {
+        for (wave, members) in [(1, 2), (2, 2), (3, 3), (5, 4), (9, 6), (20, 6)] {
+            let wave = Wave(wave);
+            assert_eq!(wave.formation_members(), members);
+
+            let mut maker = FormationMaker::default();
+            let mut rng = StdRng::seed_from_u64(0);
+            let formations: Vec<Formation> = (0..members * 3).map(|_| maker.make(&wave, &WINDOW, &mut rng)).collect();
+            // every group fills all its slots in order before the next one starts
+            for (i, formation) in formations.iter().enumerate() {
+                let i = i as u32;
+                assert_eq!(formation.size, members);
+                assert_eq!(formation.group_id, i / members + 1);
+                assert_eq!(formation.slot, i % members);
+            }
+        }
+    }
+
+    // runs one volley from a lone grunt and returns the speeds of the lasers it fired
+    fn volley_speeds(wave: u32) -> Vec<f32> {
+        let mut world = World::default();
+        world.insert_resource(Materials { muzzle_flash: vec![Handle::default()], ..Default::default() });
+        world.insert_resource(GameConfig::default());
+        world.insert_resource(Paused(false));
+        world.insert_resource(Time::default());
+        world.insert_resource(TimeScale(1.));
+        world.insert_resource(Wave(wave));
+        world.insert_resource(Difficulty::Normal);
+        world.insert_resource(ColorblindMode(false));
+        world.insert_resource(GameMode::Normal);
+        world.insert_resource(State::new(AppState::InGame));
+        world.insert_resource(GameRng::new(Some(0)));
+        // already overdue, so the volley fires without waiting on the clock
+        let mut timer = Timer::from_seconds(ENEMY_FIRE_INTERVAL as f32, true);
+        timer.set_elapsed(Duration::from_secs(60));
+        world.insert_resource(EnemyFireTimer(timer));
+        world.spawn().insert_bundle((Enemy, EnemyKind::Grunt, Transform::default()));
+
+        SystemStage::single(enemy_fire.system()).run(&mut world);
+
+        world.query_filtered::<&Speed, With<FromEnemy>>().iter(&world).map(|speed| speed.0).collect()
+    }
+
+    #[test]
+    fn enemy_lasers_speed_up_with_the_wave() {
+        let early = volley_speeds(1);
+        let late = volley_speeds(10);
+        assert_eq!((early.len(), late.len()), (1, 1));
+        assert_eq!(early[0], GameConfig::default().enemy_laser_speed);
+        assert!(late[0] > early[0]);
+    }
+
+    // Bevy keeps Time::update_with_instant to itself, so take whatever delta
+    // a bare update gives and scale it to exactly the step the test wants
+    fn step_time(world: &mut World, secs: f32) {
+        let mut time = world.get_resource_mut::<Time>().unwrap();
+        let delta = loop {
+            time.update();
+            if time.delta_seconds() > 0. {
+                break time.delta_seconds();
+            }
+        };
+        world.get_resource_mut::<TimeScale>().unwrap().0 = secs / delta;
+    }
+
+    #[test]
+    fn entering_enemy_settles_on_its_slot() {
+        let mut world = World::default();
+        world.insert_resource(Time::default());
+        world.insert_resource(TimeScale(1.));
+        world.insert_resource(Paused(false));
+        world.insert_resource(WINDOW);
+        world.insert_resource(FormationGroup::default());
+        world.insert_resource(ActiveEnemies(1));
+        world.insert_resource(GameRng::new(Some(0)));
+        let formation = Formation { start: (300., 388.), offset: (0., 100.), group_id: 1, size: 3, slot: 2 };
+        let slot = Vec2::new(formation.offset.0 + formation.slot_x(), formation.offset.1);
+        let enemy = world
+            .spawn()
+            .insert_bundle((Enemy, Transform::from_xyz(300., 388., 10.), Speed(200.), formation, MovementPhase::Entering))
+            .id();
+        let mut stage = SystemStage::single(enemy_movment.system());
+        let distance_left = |world: &World| world.get::<Transform>(enemy).unwrap().translation.truncate().distance(slot);
+
+        // 375px out, six frames of 50px each close in straight along the line
+        let mut distance = distance_left(&world);
+        for _ in 0..6 {
+            step_time(&mut world, 0.25);
+            stage.run(&mut world);
+            let now = distance_left(&world);
+            assert!((distance - now - 50.).abs() < 0.01, "moved {} instead of 50", distance - now);
+            assert_eq!(*world.get::<MovementPhase>(enemy).unwrap(), MovementPhase::Entering);
+            distance = now;
+        }
+
+        // then a frame long enough to carry it 200px, well past a slot 75px away
+        step_time(&mut world, 1.);
+        stage.run(&mut world);
+        assert_eq!(world.get::<Transform>(enemy).unwrap().translation.truncate(), slot);
+        assert_eq!(*world.get::<MovementPhase>(enemy).unwrap(), MovementPhase::Formation);
+    }
+}
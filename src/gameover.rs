@@ -0,0 +1,226 @@
+use bevy::prelude::*;
+
+use crate::{ActiveEnemies, AppState, Bombs, DailyBest, Difficulty, Explosion, FONT, GameMode, HighScore, Paused, RunStats, STARTING_BOMBS, Score, StreakState, TimeScale, date_label, today, boss::BossToSpawn, enemy::{Enemy, FromEnemy, SpawnWarning, SplitToSpawn, Wave, WaveSpawned}, missile::HomingMissile, player::{CoopMode, InputLog, Laser, Lives, PlayerStatte}, powerup::PowerUp};
+
+// score spent to get one more ship after a game over
+const CONTINUE_COST: u32 = 3000;
+
+struct GameOverText;
+struct NotEnoughScoreText;
+// set while re-entering the game from a continue, so the new-run resets
+// on entering InGame leave the wave, seed and boss rush progress alone
+#[derive(Default)]
+pub struct Continuing(pub bool);
+pub struct GameOverPlugin;
+
+impl Plugin for GameOverPlugin {
+    fn build(&self, app: &mut AppBuilder) {
+        app
+            .insert_resource(Continuing::default())
+            .add_system_set(
+                SystemSet::on_update(AppState::GameOver)
+                .with_system(continue_game.system())
+            )
+            .add_system_set(
+                SystemSet::on_update(AppState::InGame)
+                .with_system(finish_continue.system())
+            );
+        // a boss rush victory ends the run just like a game over, only with a happier title
+        for &state in &[AppState::GameOver, AppState::Victory] {
+            app
+                .add_system_set(
+                    SystemSet::on_enter(state)
+                    .with_system(game_over_setup.system())
+                )
+                .add_system_set(
+                    SystemSet::on_update(state)
+                    .with_system(restart_game.system())
+                )
+                .add_system_set(
+                    SystemSet::on_exit(state)
+                    .with_system(game_over_cleanup.system())
+                );
+        }
+    }
+}
+
+fn game_over_setup(
+    mut commands: Commands,
+    asset_server: Res<AssetServer>,
+    app_state: Res<State<AppState>>,
+    score: Res<Score>,
+    run_stats: Res<RunStats>,
+    input_log: Res<InputLog>,
+    game_mode: Res<GameMode>,
+    mut high_score: ResMut<HighScore>,
+    mut daily_best: ResMut<DailyBest>
+) {
+    if score.0 > high_score.0 {
+        high_score.0 = score.0;
+        high_score.save();
+    }
+    let daily_line = (*game_mode == GameMode::DailyChallenge).then(|| {
+        let day = today();
+        if daily_best.record(day, score.0) {
+            format!("New best for {}!", date_label(day))
+        } else {
+            format!("Best for {}: {}", date_label(day), daily_best.on(day))
+        }
+    });
+    // keep the inputs that led here around for debugging
+    input_log.save();
+
+    let title = if *app_state.current() == AppState::Victory { "VICTORY" } else { "GAME OVER" };
+    let font = asset_server.load(FONT);
+    let alignment = TextAlignment {
+        vertical: VerticalAlign::Center,
+        horizontal: HorizontalAlign::Center
+    };
+    let seconds = run_stats.time as u32;
+    let mut lines = vec![
+        (title.to_string(), 56., 120.),
+        (format!("Score: {}", score.0), 32., 65.),
+        (format!("Enemies killed: {}", run_stats.enemies_killed), 18., 25.),
+        (format!("Accuracy: {}% ({}/{})", run_stats.accuracy(), run_stats.shots_hit, run_stats.shots_fired), 18., 0.),
+        (format!("Waves cleared: {}", run_stats.waves_cleared), 18., -25.),
+        (format!("Time: {}:{:02}", seconds / 60, seconds % 60), 18., -50.)
+    ];
+    if let Some(daily_line) = daily_line {
+        lines.push((daily_line, 18., -75.));
+    }
+    lines.push(("Press R to Restart".to_string(), 24., -110.));
+    if *app_state.current() == AppState::GameOver {
+        lines.push((format!("Press C to Continue ({} points)", CONTINUE_COST), 18., -140.));
+    }
+    for (value, font_size, y) in lines.iter() {
+        commands.spawn_bundle(Text2dBundle {
+            text: Text::with_section(
+                value.as_str(),
+                TextStyle {
+                    font: font.clone(),
+                    font_size: *font_size,
+                    color: Color::WHITE
+                },
+                alignment
+            ),
+            transform: Transform::from_xyz(0., *y, 50.),
+            ..Default::default()
+        })
+        .insert(GameOverText);
+    }
+}
+
+fn restart_game(
+    mut commands: Commands,
+    keyboard_input: Res<Input<KeyCode>>,
+    mut app_state: ResMut<State<AppState>>,
+    difficulty: Res<Difficulty>,
+    coop: Res<CoopMode>,
+    mut score: ResMut<Score>,
+    mut lives: ResMut<Lives>,
+    mut wave: ResMut<Wave>,
+    mut wave_spawned: ResMut<WaveSpawned>,
+    mut active_enemies: ResMut<ActiveEnemies>,
+    mut bombs: ResMut<Bombs>,
+    mut paused: ResMut<Paused>,
+    mut time_scale: ResMut<TimeScale>,
+    mut player_state: ResMut<PlayerStatte>,
+    mut streak: ResMut<StreakState>,
+    query: Query<Entity, Or<(With<Enemy>, With<Laser>, With<Explosion>, With<PowerUp>, With<BossToSpawn>, With<HomingMissile>, With<SpawnWarning>, With<SplitToSpawn>)>>
+) {
+    if !keyboard_input.just_pressed(KeyCode::R) {
+        return;
+    }
+    // something else already queued a transition this frame, leave the run as it is
+    if app_state.set(AppState::InGame).is_err() {
+        return;
+    }
+
+    // clear whatever was still flying around when the player went down
+    for entity in query.iter() {
+        commands.entity(entity).despawn_recursive();
+    }
+
+    score.0 = 0;
+    *streak = StreakState::default();
+    *lives = Lives::new(difficulty.lives(), coop.0);
+    wave.0 = 1;
+    *wave_spawned = WaveSpawned::default();
+    active_enemies.0 = 0;
+    bombs.0 = STARTING_BOMBS;
+    paused.0 = false;
+    time_scale.0 = 1.;
+    // forget the last death so the ship comes back right away
+    *player_state = PlayerStatte::default();
+}
+
+// one more ship for a chunk of the score, the run carries on from the current wave
+fn continue_game(
+    mut commands: Commands,
+    keyboard_input: Res<Input<KeyCode>>,
+    asset_server: Res<AssetServer>,
+    mut app_state: ResMut<State<AppState>>,
+    coop: Res<CoopMode>,
+    mut score: ResMut<Score>,
+    mut lives: ResMut<Lives>,
+    mut paused: ResMut<Paused>,
+    mut player_state: ResMut<PlayerStatte>,
+    mut continuing: ResMut<Continuing>,
+    laser_query: Query<Entity, (With<Laser>, With<FromEnemy>)>,
+    not_enough_query: Query<(), With<NotEnoughScoreText>>
+) {
+    // R on the same frame wins, the run can't be restarted and continued at once
+    if !keyboard_input.just_pressed(KeyCode::C) || keyboard_input.just_pressed(KeyCode::R) {
+        return;
+    }
+
+    if score.0 < CONTINUE_COST {
+        if not_enough_query.iter().next().is_none() {
+            commands.spawn_bundle(Text2dBundle {
+                text: Text::with_section(
+                    "Not enough score",
+                    TextStyle {
+                        font: asset_server.load(FONT),
+                        font_size: 18.,
+                        color: Color::rgb(1., 0.4, 0.4)
+                    },
+                    TextAlignment {
+                        vertical: VerticalAlign::Center,
+                        horizontal: HorizontalAlign::Center
+                    }
+                ),
+                transform: Transform::from_xyz(0., -170., 50.),
+                ..Default::default()
+            })
+            .insert(GameOverText)
+            .insert(NotEnoughScoreText);
+        }
+        return;
+    }
+
+    if app_state.set(AppState::InGame).is_err() {
+        return;
+    }
+
+    // the shots that just ended the run would otherwise be waiting for the new ship
+    for entity in laser_query.iter() {
+        commands.entity(entity).despawn();
+    }
+    score.0 -= CONTINUE_COST;
+    // one ship for each player
+    *lives = Lives::new(1, coop.0);
+    paused.0 = false;
+    *player_state = PlayerStatte::default();
+    continuing.0 = true;
+}
+
+// the first InGame update, after every on_enter system has seen the flag
+fn finish_continue(mut continuing: ResMut<Continuing>) {
+    continuing.0 = false;
+}
+
+fn game_over_cleanup(mut commands: Commands, query: Query<Entity, With<GameOverText>>) {
+    for entity in query.iter() {
+        commands.entity(entity).despawn();
+    }
+}
@@ -0,0 +1,650 @@
+use bevy::prelude::*;
+
+use crate::{AppState, Bombs, CameraZoom, Difficulty, FONT, GameMode, HighScore, Materials, Paused, Score, StepMode, StreakState, SurvivalTime, WindowSize, boss::Boss, enemy::{Enemy, Health, Wave}, player::{Dash, Lives, MAX_PLAYERS, Player, PlayerId, PlayerWeapon}};
+
+struct ScoreText;
+struct BombText;
+// only shown in pacifist mode
+struct SurvivalText;
+struct PauseText;
+struct WaveBanner(Timer);
+struct BossHealthBar;
+struct BossHealthFill;
+// slot counted from the right edge, one row per player
+struct LifeIcon {
+    player: usize,
+    slot: u32
+}
+// lit up while the player holds that weapon
+struct WeaponIcon(PlayerWeapon);
+// fills back up as the dash cooldown runs
+struct DashFill;
+// pops up where an enemy died, turned into floating text next frame
+pub struct PointsToSpawn {
+    pub position: Vec3,
+    pub points: u32
+}
+struct FloatingText {
+    velocity: Vec3,
+    timer: Timer
+}
+struct Radar;
+// carried by the camera and scaled with its zoom, so the HUD sprites under it
+// keep their place and size on screen through zoom and shake
+pub struct HudRoot;
+struct RadarDot;
+struct RadarMaterials {
+    enemy_dot: Handle<ColorMaterial>,
+    player_dot: Handle<ColorMaterial>
+}
+pub struct HudPlugin;
+
+const WAVE_BANNER_TIME: f32 = 2.;
+const LIFE_ICON_SCALE: f32 = 0.25;
+const LIFE_ICON_SPACING: f32 = 42.;
+const LIFE_ICON_MARGIN: f32 = 25.;
+const LIFE_ICON_ROW_SPACING: f32 = 28.;
+const WEAPON_ICON_MARGIN: f32 = 25.;
+const WEAPON_ICON_SCALE: f32 = 1.5;
+const FLOATING_TEXT_TIME: f32 = 0.8;
+const FLOATING_TEXT_RISE: f32 = 60.;
+const CALLOUT_TIME: f32 = 1.5;
+const CALLOUT_RISE: f32 = 20.;
+// kill counts that get a callout, each replacing the one before it
+const STREAK_CALLOUTS: [(u32, &str); 6] = [
+    (2, "DOUBLE KILL"),
+    (3, "TRIPLE KILL"),
+    (5, "KILLING SPREE"),
+    (10, "RAMPAGE"),
+    (15, "UNSTOPPABLE"),
+    (25, "GODLIKE")
+];
+const RADAR_WIDTH: f32 = 80.;
+const RADAR_HEIGHT: f32 = 90.;
+const RADAR_MARGIN: f32 = 10.;
+const RADAR_DOT_SIZE: f32 = 4.;
+// world area the radar covers, in window sizes, so enemies still outside the view show up
+const RADAR_RANGE: f32 = 2.;
+
+impl Plugin for HudPlugin {
+    fn build(&self, app: &mut AppBuilder) {
+        app
+            .add_startup_system(setup_scoreboard.system())
+            .add_startup_system(setup_bomb_text.system())
+            .add_startup_system(setup_survival_text.system())
+            .add_startup_system(setup_pause_text.system())
+            .add_startup_system(setup_wave_banner.system())
+            .add_startup_system(setup_boss_health_bar.system())
+            .add_startup_system(setup_dash_meter.system())
+            // need the materials and the HUD root from the main setup
+            .add_startup_system_to_stage(StartupStage::PostStartup, setup_radar.system())
+            .add_startup_system_to_stage(StartupStage::PostStartup, setup_lives_icons.system())
+            .add_startup_system_to_stage(StartupStage::PostStartup, setup_weapon_icons.system())
+            .add_system(update_scoreboard.system())
+            .add_system(update_bomb_text.system())
+            .add_system(update_survival_text.system())
+            .add_system(update_pause_text.system())
+            .add_system(update_lives_icons.system())
+            .add_system(update_weapon_icons.system())
+            .add_system(update_radar.system())
+            .add_system(scale_hud.system())
+            .add_system(update_dash_meter.system())
+            .add_system(points_to_spawn.system())
+            .add_system(animate_floating_text.system())
+            .add_system(announce_streak.system())
+            .add_system_set(
+                SystemSet::on_update(AppState::InGame)
+                .with_system(update_wave_banner.system())
+                .with_system(update_boss_health_bar.system())
+            );
+    }
+}
+
+fn setup_scoreboard(mut commands: Commands, asset_server: Res<AssetServer>) {
+    let style = TextStyle {
+        font: asset_server.load(FONT),
+        font_size: 24.,
+        color: Color::WHITE
+    };
+    commands.spawn_bundle(TextBundle {
+        style: Style {
+            position_type: PositionType::Absolute,
+            position: Rect {
+                top: Val::Px(5.),
+                left: Val::Px(10.),
+                ..Default::default()
+            },
+            ..Default::default()
+        },
+        text: Text {
+            sections: vec![
+                TextSection {
+                    value: "Score: 0".to_string(),
+                    style: style.clone()
+                },
+                TextSection {
+                    value: "   HI: 0".to_string(),
+                    style
+                }
+            ],
+            ..Default::default()
+        },
+        ..Default::default()
+    })
+    .insert(ScoreText);
+}
+
+fn update_scoreboard(score: Res<Score>, high_score: Res<HighScore>, mut query: Query<&mut Text, With<ScoreText>>) {
+    if score.is_changed() || high_score.is_changed() {
+        for mut text in query.iter_mut() {
+            text.sections[0].value = format!("Score: {}", score.0);
+            text.sections[1].value = format!("   HI: {}", high_score.0);
+        }
+    }
+}
+
+fn setup_bomb_text(mut commands: Commands, asset_server: Res<AssetServer>, bombs: Res<Bombs>) {
+    commands.spawn_bundle(TextBundle {
+        style: Style {
+            position_type: PositionType::Absolute,
+            position: Rect {
+                top: Val::Px(32.),
+                left: Val::Px(10.),
+                ..Default::default()
+            },
+            ..Default::default()
+        },
+        text: Text::with_section(
+            format!("Bombs: {}", bombs.0),
+            TextStyle {
+                font: asset_server.load(FONT),
+                font_size: 18.,
+                color: Color::WHITE
+            },
+            Default::default()
+        ),
+        ..Default::default()
+    })
+    .insert(BombText);
+}
+
+fn update_bomb_text(bombs: Res<Bombs>, mut query: Query<&mut Text, With<BombText>>) {
+    if bombs.is_changed() {
+        for mut text in query.iter_mut() {
+            text.sections[0].value = format!("Bombs: {}", bombs.0);
+        }
+    }
+}
+
+fn setup_survival_text(mut commands: Commands, asset_server: Res<AssetServer>) {
+    commands.spawn_bundle(TextBundle {
+        style: Style {
+            position_type: PositionType::Absolute,
+            position: Rect {
+                top: Val::Px(54.),
+                left: Val::Px(10.),
+                ..Default::default()
+            },
+            ..Default::default()
+        },
+        text: Text::with_section(
+            "Time: 0s",
+            TextStyle {
+                font: asset_server.load(FONT),
+                font_size: 18.,
+                color: Color::WHITE
+            },
+            Default::default()
+        ),
+        visible: Visible {
+            is_visible: false,
+            is_transparent: true
+        },
+        ..Default::default()
+    })
+    .insert(SurvivalText);
+}
+
+fn update_survival_text(
+    survival_time: Res<SurvivalTime>,
+    game_mode: Res<GameMode>,
+    mut query: Query<(&mut Text, &mut Visible), With<SurvivalText>>
+) {
+    for (mut text, mut visible) in query.iter_mut() {
+        visible.is_visible = *game_mode == GameMode::Pacifist;
+        if survival_time.is_changed() {
+            text.sections[0].value = format!("Time: {}s", survival_time.0 as u32);
+        }
+    }
+}
+
+fn setup_pause_text(mut commands: Commands, asset_server: Res<AssetServer>) {
+    commands.spawn_bundle(Text2dBundle {
+        text: Text::with_section(
+            "PAUSED",
+            TextStyle {
+                font: asset_server.load(FONT),
+                font_size: 48.,
+                color: Color::WHITE
+            },
+            TextAlignment {
+                vertical: VerticalAlign::Center,
+                horizontal: HorizontalAlign::Center
+            }
+        ),
+        transform: Transform::from_xyz(0., 0., 50.),
+        visible: Visible {
+            is_visible: false,
+            is_transparent: true
+        },
+        ..Default::default()
+    })
+    .insert(PauseText);
+}
+
+fn update_pause_text(paused: Res<Paused>, step_mode: Res<StepMode>, mut query: Query<&mut Visible, With<PauseText>>) {
+    if paused.is_changed() || step_mode.is_changed() {
+        for mut visible in query.iter_mut() {
+            // a single frame step still counts as paused
+            visible.is_visible = paused.0 || step_mode.stepping;
+        }
+    }
+}
+
+fn setup_wave_banner(mut commands: Commands, asset_server: Res<AssetServer>) {
+    commands.spawn_bundle(Text2dBundle {
+        text: Text::with_section(
+            "",
+            TextStyle {
+                font: asset_server.load(FONT),
+                font_size: 40.,
+                color: Color::WHITE
+            },
+            TextAlignment {
+                vertical: VerticalAlign::Center,
+                horizontal: HorizontalAlign::Center
+            }
+        ),
+        transform: Transform::from_xyz(0., 60., 50.),
+        visible: Visible {
+            is_visible: false,
+            is_transparent: true
+        },
+        ..Default::default()
+    })
+    .insert(WaveBanner(Timer::from_seconds(WAVE_BANNER_TIME, false)));
+}
+
+fn update_wave_banner(
+    time: Res<Time>,
+    wave: Res<Wave>,
+    mut query: Query<(&mut Text, &mut Visible, &mut WaveBanner)>
+) {
+    for (mut text, mut visible, mut banner) in query.iter_mut() {
+        if wave.is_changed() {
+            text.sections[0].value = format!("Wave {}", wave.0);
+            visible.is_visible = true;
+            banner.0.reset();
+        }
+
+        banner.0.tick(time.delta());
+        if banner.0.finished() {
+            visible.is_visible = false;
+        }
+    }
+}
+
+fn setup_boss_health_bar(mut commands: Commands, mut materials: ResMut<Assets<ColorMaterial>>) {
+    let hidden = Visible {
+        is_visible: false,
+        is_transparent: true
+    };
+    commands.spawn_bundle(NodeBundle {
+        style: Style {
+            position_type: PositionType::Absolute,
+            position: Rect {
+                top: Val::Px(10.),
+                left: Val::Percent(25.),
+                ..Default::default()
+            },
+            size: Size::new(Val::Percent(50.), Val::Px(14.)),
+            ..Default::default()
+        },
+        material: materials.add(Color::rgb(0.2, 0.2, 0.2).into()),
+        visible: hidden.clone(),
+        ..Default::default()
+    })
+    .insert(BossHealthBar)
+    .with_children(|parent| {
+        parent.spawn_bundle(NodeBundle {
+            style: Style {
+                size: Size::new(Val::Percent(100.), Val::Percent(100.)),
+                ..Default::default()
+            },
+            material: materials.add(Color::rgb(0.85, 0.1, 0.1).into()),
+            visible: hidden,
+            ..Default::default()
+        })
+        .insert(BossHealthFill);
+    });
+}
+
+fn update_boss_health_bar(
+    boss_query: Query<&Health, With<Boss>>,
+    mut fill_query: Query<&mut Style, With<BossHealthFill>>,
+    mut visible_query: Query<&mut Visible, Or<(With<BossHealthBar>, With<BossHealthFill>)>>
+) {
+    // visibility doesn't propagate to children, so toggle both nodes
+    let boss = boss_query.iter().next();
+    for mut visible in visible_query.iter_mut() {
+        visible.is_visible = boss.is_some();
+    }
+    if let Some(health) = boss {
+        for mut style in fill_query.iter_mut() {
+            style.size.width = Val::Percent(100. * health.current as f32 / health.max as f32);
+        }
+    }
+}
+
+fn setup_dash_meter(mut commands: Commands, mut materials: ResMut<Assets<ColorMaterial>>) {
+    // bottom left, clear of the weapon icon on the other side
+    commands.spawn_bundle(NodeBundle {
+        style: Style {
+            position_type: PositionType::Absolute,
+            position: Rect {
+                bottom: Val::Px(10.),
+                left: Val::Px(10.),
+                ..Default::default()
+            },
+            size: Size::new(Val::Px(60.), Val::Px(6.)),
+            ..Default::default()
+        },
+        material: materials.add(Color::rgb(0.2, 0.2, 0.2).into()),
+        ..Default::default()
+    })
+    .with_children(|parent| {
+        parent.spawn_bundle(NodeBundle {
+            style: Style {
+                size: Size::new(Val::Percent(100.), Val::Percent(100.)),
+                ..Default::default()
+            },
+            material: materials.add(Color::rgb(0.3, 0.8, 1.).into()),
+            ..Default::default()
+        })
+        .insert(DashFill);
+    });
+}
+
+// the meter and weapon icon follow the first player
+fn update_dash_meter(dash_query: Query<(&Dash, &PlayerId), With<Player>>, mut fill_query: Query<&mut Style, With<DashFill>>) {
+    // a fresh ship comes with its dash ready, so the meter just stays put while respawning
+    if let Some((dash, _)) = dash_query.iter().find(|(_, id)| id.0 == 0) {
+        for mut style in fill_query.iter_mut() {
+            style.size.width = Val::Percent(100. * dash.ready_percent());
+        }
+    }
+}
+
+fn setup_lives_icons(mut commands: Commands, materials: Res<Materials>, hud_query: Query<Entity, With<HudRoot>>) {
+    let hud_root = hud_query.single().unwrap();
+    // enough slots for the most generous difficulty, each row showing that player's ship
+    commands.entity(hud_root).with_children(|hud| {
+        for player in 0..MAX_PLAYERS {
+            let material = if player == 0 { materials.player.clone() } else { materials.player_two.clone() };
+            for slot in 0..Difficulty::Easy.lives() {
+                hud.spawn_bundle(SpriteBundle {
+                    material: material.clone(),
+                    transform: Transform {
+                        scale: Vec3::new(LIFE_ICON_SCALE, LIFE_ICON_SCALE, 1.),
+                        ..Default::default()
+                    },
+                    ..Default::default()
+                })
+                .insert(LifeIcon { player, slot });
+            }
+        }
+    });
+}
+
+fn update_lives_icons(
+    lives: Res<Lives>,
+    window_size: Res<WindowSize>,
+    mut query: Query<(&mut Transform, &mut Visible, &LifeIcon)>
+) {
+    for (mut tf, mut visible, icon) in query.iter_mut() {
+        if window_size.is_changed() || tf.is_added() {
+            tf.translation = Vec3::new(
+                window_size.width / 2. - LIFE_ICON_MARGIN - icon.slot as f32 * LIFE_ICON_SPACING,
+                window_size.height / 2. - LIFE_ICON_MARGIN - icon.player as f32 * LIFE_ICON_ROW_SPACING,
+                50.
+            );
+        }
+        if lives.is_changed() || tf.is_added() {
+            visible.is_visible = icon.slot < lives.0[icon.player];
+        }
+    }
+}
+
+fn setup_weapon_icons(mut commands: Commands, materials: Res<Materials>, hud_query: Query<Entity, With<HudRoot>>) {
+    let hud_root = hud_query.single().unwrap();
+    commands.entity(hud_root).with_children(|hud| {
+        hud.spawn_bundle(SpriteBundle {
+            material: materials.player_laser.clone(),
+            ..Default::default()
+        })
+        .insert(WeaponIcon(PlayerWeapon::Laser));
+
+        hud.spawn_bundle(SpriteBundle {
+            material: materials.missile.clone(),
+            sprite: Sprite::new(Vec2::new(4., 11.)),
+            visible: Visible {
+                is_visible: false,
+                is_transparent: true
+            },
+            ..Default::default()
+        })
+        .insert(WeaponIcon(PlayerWeapon::Missiles));
+    });
+}
+
+// bottom right corner, the last weapon held stays shown while the ship respawns
+fn update_weapon_icons(
+    window_size: Res<WindowSize>,
+    player_query: Query<(&PlayerWeapon, &PlayerId), With<Player>>,
+    mut query: Query<(&mut Transform, &mut Visible, &WeaponIcon)>
+) {
+    let held = player_query.iter().find(|(_, id)| id.0 == 0).map(|(weapon, _)| weapon);
+    for (mut tf, mut visible, icon) in query.iter_mut() {
+        if window_size.is_changed() || tf.is_added() {
+            tf.translation = Vec3::new(
+                window_size.width / 2. - WEAPON_ICON_MARGIN,
+                -window_size.height / 2. + WEAPON_ICON_MARGIN,
+                50.
+            );
+            tf.scale = Vec3::new(WEAPON_ICON_SCALE, WEAPON_ICON_SCALE, 1.);
+        }
+        if let Some(held) = held {
+            visible.is_visible = icon.0 == *held;
+        }
+    }
+}
+
+fn setup_radar(mut commands: Commands, mut materials: ResMut<Assets<ColorMaterial>>, hud_query: Query<Entity, With<HudRoot>>) {
+    let radar = commands.spawn_bundle(SpriteBundle {
+        material: materials.add(Color::rgba(0.2, 0.8, 0.3, 0.15).into()),
+        sprite: Sprite::new(Vec2::new(RADAR_WIDTH, RADAR_HEIGHT)),
+        visible: Visible {
+            is_visible: true,
+            is_transparent: true
+        },
+        ..Default::default()
+    })
+    .insert(Radar)
+    .id();
+    commands.entity(hud_query.single().unwrap()).push_children(&[radar]);
+
+    commands.insert_resource(RadarMaterials {
+        enemy_dot: materials.add(Color::rgb(1., 0.3, 0.3).into()),
+        player_dot: materials.add(Color::rgb(0.3, 0.9, 1.).into())
+    });
+}
+
+fn update_radar(
+    mut commands: Commands,
+    window_size: Res<WindowSize>,
+    radar_materials: Res<RadarMaterials>,
+    hud_query: Query<Entity, With<HudRoot>>,
+    mut radar_query: Query<&mut Transform, (With<Radar>, Without<RadarDot>)>,
+    mut dot_query: Query<(Entity, &mut Transform, &mut Handle<ColorMaterial>), With<RadarDot>>,
+    enemy_query: Query<&Transform, (With<Enemy>, Without<Radar>, Without<RadarDot>)>,
+    player_query: Query<&Transform, (With<Player>, Without<Radar>, Without<RadarDot>)>
+) {
+    // bottom-left corner
+    let center = Vec2::new(
+        -window_size.width / 2. + RADAR_MARGIN + RADAR_WIDTH / 2.,
+        -window_size.height / 2. + RADAR_MARGIN + RADAR_HEIGHT / 2.
+    );
+    for mut tf in radar_query.iter_mut() {
+        tf.translation = Vec3::new(center.x, center.y, 40.);
+    }
+
+    let to_radar = |world: Vec3| {
+        let x = (world.x / (window_size.width * RADAR_RANGE) * RADAR_WIDTH).max(-RADAR_WIDTH / 2.).min(RADAR_WIDTH / 2.);
+        let y = (world.y / (window_size.height * RADAR_RANGE) * RADAR_HEIGHT).max(-RADAR_HEIGHT / 2.).min(RADAR_HEIGHT / 2.);
+        Vec3::new(center.x + x, center.y + y, 41.)
+    };
+    let mut blips = player_query
+        .iter()
+        .map(|tf| (to_radar(tf.translation), &radar_materials.player_dot))
+        .chain(enemy_query.iter().map(|tf| (to_radar(tf.translation), &radar_materials.enemy_dot)));
+
+    // reuse the dots we have, then top up or trim to match the blip count
+    for (entity, mut tf, mut material) in dot_query.iter_mut() {
+        match blips.next() {
+            Some((position, dot_material)) => {
+                tf.translation = position;
+                if *material != *dot_material {
+                    *material = dot_material.clone();
+                }
+            }
+            // recursive so the HUD root forgets it
+            None => commands.entity(entity).despawn_recursive()
+        }
+    }
+    let hud_root = match hud_query.single() {
+        Ok(hud_root) => hud_root,
+        Err(_) => return
+    };
+    commands.entity(hud_root).with_children(|hud| {
+        for (position, dot_material) in blips {
+            hud.spawn_bundle(SpriteBundle {
+                material: dot_material.clone(),
+                sprite: Sprite::new(Vec2::new(RADAR_DOT_SIZE, RADAR_DOT_SIZE)),
+                transform: Transform::from_translation(position),
+                ..Default::default()
+            })
+            .insert(RadarDot);
+        }
+    });
+}
+
+// the HUD's window-edge positions and sizes stretch with the view
+fn scale_hud(zoom: Res<CameraZoom>, mut query: Query<&mut Transform, With<HudRoot>>) {
+    if !zoom.is_changed() {
+        return;
+    }
+    for mut tf in query.iter_mut() {
+        tf.scale = Vec3::new(zoom.0, zoom.0, 1.);
+    }
+}
+
+fn spawn_floating_text(commands: &mut Commands, font: Handle<Font>, value: String, font_size: f32, color: Color, position: Vec3, rise: f32, time: f32) {
+    commands.spawn_bundle(Text2dBundle {
+        text: Text::with_section(
+            value,
+            TextStyle {
+                font,
+                font_size,
+                color
+            },
+            TextAlignment {
+                vertical: VerticalAlign::Center,
+                horizontal: HorizontalAlign::Center
+            }
+        ),
+        transform: Transform::from_xyz(position.x, position.y, 50.),
+        ..Default::default()
+    })
+    .insert(FloatingText {
+        velocity: Vec3::new(0., rise, 0.),
+        timer: Timer::from_seconds(time, false)
+    });
+}
+
+fn points_to_spawn(mut commands: Commands, asset_server: Res<AssetServer>, query: Query<(Entity, &PointsToSpawn)>) {
+    for (entity, points_to_spawn) in query.iter() {
+        // a bit bigger for the big scores
+        let font_size = if points_to_spawn.points >= 1000 { 28. } else { 18. };
+        spawn_floating_text(
+            &mut commands,
+            asset_server.load(FONT),
+            format!("+{}", points_to_spawn.points),
+            font_size,
+            Color::WHITE,
+            points_to_spawn.position,
+            FLOATING_TEXT_RISE,
+            FLOATING_TEXT_TIME
+        );
+
+        commands.entity(entity).despawn();
+    }
+}
+
+fn announce_streak(
+    mut commands: Commands,
+    asset_server: Res<AssetServer>,
+    streak: Res<StreakState>,
+    mut last_kills: Local<u32>
+) {
+    if !streak.is_changed() {
+        return;
+    }
+    // several kills can land in one frame, only the highest callout crossed shows
+    let crossed = STREAK_CALLOUTS
+        .iter()
+        .rev()
+        .find(|(kills, _)| *last_kills < *kills && *kills <= streak.kills_since_death);
+    if let Some((_, callout)) = crossed {
+        spawn_floating_text(
+            &mut commands,
+            asset_server.load(FONT),
+            callout.to_string(),
+            36.,
+            Color::rgb(1., 0.8, 0.2),
+            Vec3::new(0., 120., 0.),
+            CALLOUT_RISE,
+            CALLOUT_TIME
+        );
+    }
+    *last_kills = streak.kills_since_death;
+}
+
+fn animate_floating_text(
+    mut commands: Commands,
+    time: Res<Time>,
+    paused: Res<Paused>,
+    mut query: Query<(Entity, &mut Transform, &mut Text, &mut FloatingText)>
+) {
+    if paused.0 {
+        return;
+    }
+    for (entity, mut tf, mut text, mut floating) in query.iter_mut() {
+        floating.timer.tick(time.delta());
+        if floating.timer.finished() {
+            commands.entity(entity).despawn();
+            continue;
+        }
+        tf.translation += floating.velocity * time.delta_seconds();
+        text.sections[0].style.color.set_a(1. - floating.timer.percent());
+    }
+}
@@ -1,33 +1,467 @@
-use std::collections::HashSet;
+#![allow(clippy::type_complexity, clippy::too_many_arguments)]
 
-use bevy::{prelude::*, sprite::collide_aabb::collide};
-use enemy::{ActiveEnemies, Enemy, EnemyPlugin, FromEnemy};
-use player::{FromPlayer, Laser, Player, PlayerPlugin, PlayerStatte};
+use std::{collections::HashSet, fs, path::PathBuf, str::FromStr, time::{SystemTime, UNIX_EPOCH}};
+
+use bevy::{ecs::schedule::ShouldRun, input::InputSystem, prelude::*, render::camera::{Camera, CameraProjection, OrthographicProjection}, sprite::collide_aabb::collide, window::{WindowId, WindowMode, WindowResized}};
+use boss::{Boss, BossPlugin, WeakPoint};
+use debug::DebugPlugin;
+use enemy::{ActiveEnemies, Berserk, Enemy, EnemyKind, EnemyPlugin, FromEnemy, Health, Shielded, SplitToSpawn, Wave};
+use gameover::{Continuing, GameOverPlugin};
+use hud::{HudPlugin, HudRoot, PointsToSpawn};
+use menu::MenuPlugin;
+use missile::MissilePlugin;
+use music::MusicPlugin;
+use player::{ChargedLaser, Dash, FromPlayer, Invulnerable, Laser, Lives, PLAYER_LIVES, Piercing, Player, PlayerId, PlayerPlugin, PlayerStatte};
+use powerup::{PowerUp, PowerUpPlugin, PowerUpToSpawn, Shield};
+use rand::{Rng, SeedableRng, rngs::StdRng, thread_rng};
+use rumble::{RumblePlugin, RumbleToPlay, rumble};
+use settings::SettingsPlugin;
+use serde::{Deserialize, Serialize, de::DeserializeOwned};
 
 mod player;
 mod enemy;
+mod boss;
+mod debug;
+mod gameover;
+mod hud;
+mod menu;
+mod missile;
+mod music;
+mod powerup;
+mod rumble;
+mod settings;
 
 const PLAYER_SPRITE: &str = "player_a_01.png";
+const PLAYER_TWO_SPRITE: &str = "player_b_01.png";
 const PLAYER_LASER_SPRITE: &str = "laser_a_01.png";
 const ENEMY_LASER_SPRITE: &str = "laser_b_01.png";
-const ENEMY_SPRITE: &str = "enemy_a_01.png";
+// greyscale copies of the lasers for tinting in colorblind mode
+const PLAYER_LASER_MONO_SPRITE: &str = "laser_a_01_mono.png";
+const ENEMY_LASER_MONO_SPRITE: &str = "laser_b_01_mono.png";
+// grunt, diver, tank and boss side by side, all the same size
+const ENEMY_SHEET: &str = "enemy_sheet.png";
+const ENEMY_FRAME_WIDTH: f32 = 93.;
+const ENEMY_FRAME_HEIGHT: f32 = 84.;
+const ENEMY_FRAMES: usize = 4;
 const EXPLOSION_SHEET: &str = "explo_a_sheet.png";
-const TIME_PER_FRAME: f32 = 1. / 60.;
+const SHIELD_SPRITE: &str = "shield.png";
+const WARNING_SPRITE: &str = "warning_arrow.png";
+const WEAK_POINT_SPRITE: &str = "weak_point.png";
+const MUZZLE_FLASH_SPRITE: &str = "muzzle_flash.png";
+// one row of flame frames, cycled by animate_thruster
+const THRUSTER_SHEET: &str = "thruster_sheet.png";
+const THRUSTER_FRAME_WIDTH: f32 = 32.;
+const THRUSTER_FRAME_HEIGHT: f32 = 48.;
+const THRUSTER_FRAMES: usize = 4;
+// tiles vertically, two copies are stacked and scrolled by scroll_background
+const BACKGROUND_SPRITE: &str = "background.png";
+const BACKGROUND_SIZE: f32 = 512.;
+const BACKGROUND_SPEED: f32 = 40.;
+const CONFIG_FILE: &str = "assets/config.ron";
+const HIGH_SCORE_FILE: &str = "highscore.json";
+const DAILY_BEST_FILE: &str = "daily.json";
+const SECONDS_PER_DAY: u64 = 86_400;
+const FONT: &str = "DejaVuSansMono-Bold.ttf";
 const SCALE: f32 = 0.5;
+const ENEMY_POINTS: u32 = 100;
+const CHARGED_LASER_DAMAGE: u32 = 3;
+// multiplies a laser's damage when it lands on the boss's weak point
+const WEAK_POINT_DAMAGE: u32 = 3;
+const POWERUP_DROP_CHANCE: f64 = 0.15;
+const STARTING_BOMBS: u32 = 2;
+const BOSS_KILL_BONUS: u32 = 5000;
+const GRAZE_POINTS: u32 = 10;
+const SURVIVAL_POINTS_PER_SECOND: f32 = 50.;
+// how close past the ship's box an enemy laser has to come to count as a graze
+const GRAZE_MARGIN: f32 = 20.;
+const SHAKE_DURATION: f32 = 0.4;
+const LASER_TRAIL_STEPS: u32 = 4;
+const MUZZLE_FLASH_STEPS: u32 = 4;
+const MUZZLE_FLASH_TIME: f32 = 0.1;
+const SHAKE_MAGNITUDE: f32 = 12.;
+// the camera pulls back as the screen fills up. Kept small so what it shows past
+// the window edge stays inside OFFSCREEN_MARGIN, where lasers still live
+const MAX_ZOOM: f32 = 1.1;
+const ZOOM_FULL_ENEMIES: f32 = 10.;
+// how quickly the zoom closes in on its target, per second
+const ZOOM_RATE: f32 = 1.5;
+const FLASH_DURATION: f32 = 0.15;
+const BOSS_FLASH: Color = Color::rgba(1., 0.9, 0.7, 0.35);
+const PLAYER_FLASH: Color = Color::rgba(1., 0.3, 0.2, 0.3);
+// how far past the window edge projectiles and drops live on before being removed
+const OFFSCREEN_MARGIN: f32 = 50.;
 
 pub struct Speed(f32);
-impl Default for Speed {
+
+/// Balance knobs read from `assets/config.ron`, any missing field keeps its default.
+#[derive(Deserialize)]
+#[serde(default)]
+pub struct GameConfig {
+    // speeds are in pixels per second: lasers clearly outrun the ship,
+    // and enemies are a bit slower than the player
+    player_speed: f32,
+    // base for grunts, divers and tanks scale off it
+    enemy_speed: f32,
+    max_enemies: u32,
+    respawn_delay: f64,
+    laser_speed: f32,
+    charged_laser_speed: f32,
+    // slower than the player's shots so they stay dodgeable
+    enemy_laser_speed: f32,
+    // layout of the explosion sprite sheet
+    explosion_frame_width: f32,
+    explosion_frame_height: f32,
+    explosion_columns: usize,
+    explosion_rows: usize
+}
+
+impl Default for GameConfig {
     fn default() -> Self {
-        Self (500.)
+        Self {
+            player_speed: 400.,
+            enemy_speed: 350.,
+            max_enemies: 5,
+            respawn_delay: 2.,
+            laser_speed: 700.,
+            charged_laser_speed: 900.,
+            enemy_laser_speed: 450.,
+            explosion_frame_width: 64.,
+            explosion_frame_height: 64.,
+            explosion_columns: 4,
+            explosion_rows: 4
+        }
     }
 }
 
+impl GameConfig {
+    fn load() -> Self {
+        match fs::read_to_string(CONFIG_FILE) {
+            Ok(contents) => ron::de::from_str(&contents).unwrap_or_else(|err| {
+                warn!("ignoring invalid {}: {}", CONFIG_FILE, err);
+                Self::default()
+            }),
+            Err(_) => Self::default()
+        }
+    }
+}
+
+#[cfg_attr(test, derive(Default))]
 pub struct Materials {
     player: Handle<ColorMaterial>,
+    // the second ship in co-op
+    player_two: Handle<ColorMaterial>,
     player_laser: Handle<ColorMaterial>,
+    // the player laser at falling opacity, most opaque first
+    laser_trail: Vec<Handle<ColorMaterial>>,
+    // the muzzle flash glow at falling opacity, most opaque first
+    muzzle_flash: Vec<Handle<ColorMaterial>>,
     enemy_laser: Handle<ColorMaterial>,
-    enemy: Handle<ColorMaterial>,
-    explosion: Handle<TextureAtlas>
+    // high-contrast cyan and yellow lasers for colorblind mode
+    player_laser_colorblind: Handle<ColorMaterial>,
+    enemy_laser_colorblind: Handle<ColorMaterial>,
+    // every enemy and the boss, picked by frame
+    enemies: Handle<TextureAtlas>,
+    weak_point: Handle<ColorMaterial>,
+    explosion: Handle<TextureAtlas>,
+    thruster: Handle<TextureAtlas>,
+    powerup_speed: Handle<ColorMaterial>,
+    powerup_shield: Handle<ColorMaterial>,
+    powerup_spread: Handle<ColorMaterial>,
+    powerup_piercing: Handle<ColorMaterial>,
+    powerup_bullet_time: Handle<ColorMaterial>,
+    shield: Handle<ColorMaterial>,
+    missile: Handle<ColorMaterial>,
+    spawn_warning: Handle<ColorMaterial>,
+    background: Handle<ColorMaterial>
+}
+
+impl Materials {
+    fn player_laser(&self, colorblind: &ColorblindMode) -> Handle<ColorMaterial> {
+        if colorblind.0 { self.player_laser_colorblind.clone() } else { self.player_laser.clone() }
+    }
+
+    fn enemy_laser(&self, colorblind: &ColorblindMode) -> Handle<ColorMaterial> {
+        if colorblind.0 { self.enemy_laser_colorblind.clone() } else { self.enemy_laser.clone() }
+    }
+}
+
+pub struct Score(u32);
+pub struct Paused(bool);
+// set for the one frame that `.` lets run while paused
+#[derive(Default)]
+pub struct StepMode {
+    stepping: bool
+}
+pub struct Bombs(u32);
+// seconds alive this run, counted in pacifist mode
+pub struct SurvivalTime(f32);
+// tallies for the results on the game over screen, carried over a continue
+#[derive(Default)]
+pub struct RunStats {
+    // player lasers only, missiles home in and would flatter the accuracy
+    shots_fired: u32,
+    // lasers that hit at least one enemy
+    shots_hit: u32,
+    enemies_killed: u32,
+    waves_cleared: u32,
+    // seconds in game, not counting pauses
+    time: f32
+}
+
+impl RunStats {
+    // percent of lasers fired that hit something
+    fn accuracy(&self) -> u32 {
+        if self.shots_fired == 0 { 0 } else { self.shots_hit * 100 / self.shots_fired }
+    }
+}
+// enemies downed since the player last lost a ship, for the streak callouts
+#[derive(Default)]
+pub struct StreakState {
+    kills_since_death: u32
+}
+// multiplies the frame delta for enemies and their lasers
+pub struct TimeScale(f32);
+// swaps the blue and pink lasers for cyan and yellow, toggled on the menu
+pub struct ColorblindMode(pub bool);
+// seeded randomness for anything that should replay the same way, e.g. spawn patterns
+pub struct GameRng {
+    seed: u64,
+    rng: StdRng
+}
+
+impl GameRng {
+    // without a seed a fresh one is drawn, reseed logs it so a run can be repeated
+    fn new(seed: Option<u64>) -> Self {
+        let seed = seed.unwrap_or_else(|| thread_rng().gen());
+        Self { seed, rng: StdRng::seed_from_u64(seed) }
+    }
+
+    // the title screen demo draws from it too, so every game starts over from the seed
+    fn reseed(&mut self) {
+        info!("rng seed: {}", self.seed);
+        self.rng = StdRng::seed_from_u64(self.seed);
+    }
+
+    // everyone playing on the same day gets the same run, --seed doesn't apply
+    fn reseed_daily(&mut self, day: u64) {
+        let seed = daily_seed(day);
+        info!("daily seed for {}: {}", date_label(day), seed);
+        self.rng = StdRng::seed_from_u64(seed);
+    }
+}
+
+// days since the unix epoch in UTC, so the daily challenge rolls over at the same moment everywhere
+fn today() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |since| since.as_secs() / SECONDS_PER_DAY)
+}
+
+// splitmix64, so neighbouring days don't start out alike
+fn daily_seed(day: u64) -> u64 {
+    let mut z = day.wrapping_add(0x9E37_79B9_7F4A_7C15);
+    z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+    z ^ (z >> 31)
+}
+
+// YYYY-MM-DD for a day count, Howard Hinnant's civil_from_days
+fn date_label(day: u64) -> String {
+    let z = day as i64 + 719_468;
+    let era = z / 146_097;
+    let day_of_era = z - era * 146_097;
+    let year_of_era = (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    // months counted from March so the leap day comes last
+    let mp = (5 * day_of_year + 2) / 153;
+    let day_of_month = day_of_year - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = year_of_era + era * 400 + i64::from(month <= 2);
+    format!("{}-{:02}-{:02}", year, month, day_of_month)
+}
+#[derive(Default, Serialize, Deserialize)]
+pub struct HighScore(u32);
+
+impl HighScore {
+    fn load() -> Self {
+        load_json(HIGH_SCORE_FILE)
+    }
+
+    fn save(&self) {
+        save_json(HIGH_SCORE_FILE, self);
+    }
+}
+
+// the best daily challenge score, only counts for the day it was set on
+#[derive(Default, Serialize, Deserialize)]
+pub struct DailyBest {
+    day: u64,
+    score: u32
+}
+
+impl DailyBest {
+    fn load() -> Self {
+        load_json(DAILY_BEST_FILE)
+    }
+
+    fn on(&self, day: u64) -> u32 {
+        if self.day == day { self.score } else { 0 }
+    }
+
+    // true when the score beats what was set earlier that day
+    fn record(&mut self, day: u64, score: u32) -> bool {
+        if score <= self.on(day) {
+            return false;
+        }
+        self.day = day;
+        self.score = score;
+        save_json(DAILY_BEST_FILE, self);
+        true
+    }
+}
+
+// saved state lives in the per-user data dir, e.g. ~/.local/share/rust-game
+fn data_path(file: &str) -> Option<PathBuf> {
+    dirs::data_dir().map(|dir| dir.join("rust-game").join(file))
+}
+
+// a missing or unreadable file just means nothing was saved yet
+fn load_json<T: DeserializeOwned + Default>(file: &str) -> T {
+    data_path(file)
+        .and_then(|path| fs::read_to_string(path).ok())
+        .and_then(|json| serde_json::from_str(&json).ok())
+        .unwrap_or_default()
+}
+
+fn save_json<T: Serialize>(file: &str, value: &T) {
+    let path = match data_path(file) {
+        Some(path) => path,
+        None => return
+    };
+    let result = path
+        .parent()
+        .map_or(Ok(()), fs::create_dir_all)
+        .and_then(|_| fs::write(&path, serde_json::to_string(value).unwrap()));
+    if let Err(err) = result {
+        warn!("could not save {}: {}", path.display(), err);
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Difficulty {
+    Easy,
+    Normal,
+    Hard
+}
+
+impl Default for Difficulty {
+    fn default() -> Self {
+        Difficulty::Normal
+    }
+}
+
+impl Difficulty {
+    fn name(&self) -> &'static str {
+        match self {
+            Difficulty::Easy => "Easy",
+            Difficulty::Normal => "Normal",
+            Difficulty::Hard => "Hard"
+        }
+    }
+
+    fn max_enemies(&self, base: u32) -> u32 {
+        match self {
+            Difficulty::Easy => base.saturating_sub(2).max(1),
+            Difficulty::Normal => base,
+            Difficulty::Hard => base + 2
+        }
+    }
+
+    fn speed_scale(&self) -> f32 {
+        match self {
+            Difficulty::Easy => 0.8,
+            Difficulty::Normal => 1.,
+            Difficulty::Hard => 1.25
+        }
+    }
+
+    // multiplies the time between enemy volleys
+    fn fire_scale(&self) -> f64 {
+        match self {
+            Difficulty::Easy => 1.4,
+            Difficulty::Normal => 1.,
+            Difficulty::Hard => 0.7
+        }
+    }
+
+    fn lives(&self) -> u32 {
+        match self {
+            Difficulty::Easy => PLAYER_LIVES + 2,
+            Difficulty::Normal => PLAYER_LIVES,
+            Difficulty::Hard => PLAYER_LIVES - 1
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum GameMode {
+    Normal,
+    // no waves, just one boss after another
+    BossRush,
+    // a still grid of enemies that never fire, for trying out weapons
+    Practice,
+    // the usual waves with nothing to shoot back, points come from staying alive
+    Pacifist,
+    // the usual waves seeded from the date, the same run for everyone that day
+    DailyChallenge
+}
+
+impl Default for GameMode {
+    fn default() -> Self {
+        GameMode::Normal
+    }
+}
+
+impl GameMode {
+    fn name(&self) -> &'static str {
+        match self {
+            GameMode::Normal => "Normal",
+            GameMode::BossRush => "Boss Rush",
+            GameMode::Practice => "Practice",
+            GameMode::Pacifist => "Pacifist",
+            GameMode::DailyChallenge => "Daily Challenge"
+        }
+    }
+
+    fn next(&self) -> Self {
+        match self {
+            GameMode::Normal => GameMode::BossRush,
+            GameMode::BossRush => GameMode::Practice,
+            GameMode::Practice => GameMode::Pacifist,
+            GameMode::Pacifist => GameMode::DailyChallenge,
+            GameMode::DailyChallenge => GameMode::Normal
+        }
+    }
+
+    // the modes that run the regular wave spawner
+    fn has_waves(&self) -> bool {
+        matches!(self, GameMode::Normal | GameMode::Pacifist | GameMode::DailyChallenge)
+    }
+
+    fn can_fire(&self) -> bool {
+        *self != GameMode::Pacifist
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum AppState {
+    MainMenu,
+    InGame,
+    GameOver,
+    // the last boss of a boss rush went down
+    Victory,
+    // rebinding keys, reached from the menu
+    Settings
 }
 
 pub struct WindowSize {
@@ -35,142 +469,1095 @@ pub struct WindowSize {
     height: f32
 }
 
+struct MainCamera;
+struct Background;
+// the main camera's projection scale, 1 shows exactly the window
+pub struct CameraZoom(pub f32);
+
+pub struct ScreenShake {
+    timer: Timer,
+    magnitude: f32
+}
+
+impl Default for ScreenShake {
+    fn default() -> Self {
+        Self {
+            timer: Timer::from_seconds(SHAKE_DURATION, false),
+            magnitude: 0.
+        }
+    }
+}
+
+impl ScreenShake {
+    fn start(&mut self, magnitude: f32) {
+        self.timer.reset();
+        self.magnitude = magnitude;
+    }
+}
+
+// a fullscreen tint that fades out, for the biggest explosions
+pub struct ScreenFlash {
+    timer: Timer,
+    color: Color
+}
+struct ScreenFlashOverlay;
+
+impl Default for ScreenFlash {
+    fn default() -> Self {
+        // start out finished so nothing flashes on startup
+        let mut timer = Timer::from_seconds(FLASH_DURATION, false);
+        timer.tick(timer.duration());
+        Self { timer, color: Color::NONE }
+    }
+}
+
+impl ScreenFlash {
+    fn start(&mut self, color: Color) {
+        self.timer.reset();
+        self.color = color;
+    }
+}
+
+// sent when an enemy is destroyed, the score and effects systems pick it up
+pub struct EnemyKilled {
+    pub position: Vec3,
+    // None for the boss
+    pub kind: Option<EnemyKind>,
+    pub boss: bool,
+    pub max_health: u32
+}
+
+pub struct PlayerDied {
+    pub position: Vec3
+}
+
+// a short glow where a shot was fired
+struct MuzzleFlash(Timer);
+
 struct Explosion;
-struct ExplosionToSpawn(Vec3);
+struct ExplosionToSpawn {
+    position: Vec3,
+    scale: f32,
+    // seconds per sheet frame
+    frame_time: f32,
+    flash: Option<Color>
+}
+
+impl ExplosionToSpawn {
+    fn new(position: Vec3) -> Self {
+        Self { position, scale: 1., frame_time: 0.05, flash: None }
+    }
+
+    // quick pop for the one-hit enemies
+    fn small(position: Vec3) -> Self {
+        Self { position, scale: 0.7, frame_time: 0.035, flash: None }
+    }
+
+    fn spark(position: Vec3) -> Self {
+        Self { position, scale: 0.3, frame_time: 0.02, flash: None }
+    }
+
+    fn large(position: Vec3) -> Self {
+        Self { position, scale: 2.5, frame_time: 0.08, flash: None }
+    }
+
+    fn with_flash(self, color: Color) -> Self {
+        Self { flash: Some(color), ..self }
+    }
+}
+
+// Recognized command-line flags, each followed by its value:
+//   --width <px>   window width, defaults to 600
+//   --height <px>  window height, defaults to 680
+//   --seed <n>     seed for GameRng, random when left out
+// Unknown flags are ignored and invalid values fall back to the default.
+struct CliArgs {
+    width: f32,
+    height: f32,
+    seed: Option<u64>
+}
+
+impl CliArgs {
+    fn parse() -> Self {
+        let args: Vec<String> = std::env::args().skip(1).collect();
+        Self {
+            width: cli_value(&args, "--width").filter(|width: &f32| *width > 0.).unwrap_or(600.),
+            height: cli_value(&args, "--height").filter(|height: &f32| *height > 0.).unwrap_or(680.),
+            seed: cli_value(&args, "--seed")
+        }
+    }
+}
+
+fn cli_value<T: FromStr>(args: &[String], flag: &str) -> Option<T> {
+    let value = args.iter().skip_while(|arg| *arg != flag).nth(1)?;
+    let parsed = value.parse().ok();
+    if parsed.is_none() {
+        // the logger isn't set up this early
+        eprintln!("ignoring invalid value for {}: {}", flag, value);
+    }
+    parsed
+}
 
 fn main() {
+    let args = CliArgs::parse();
     let window_descriptor = WindowDescriptor {
         title: "Rust Game".to_string(),
-        width: 600.0,
-        height: 680.0,
+        width: args.width,
+        height: args.height,
         ..Default::default()
     };
 
     App::build()
         .insert_resource(ClearColor(Color::rgb(0.04, 0.04, 0.04)))
         .insert_resource(ActiveEnemies(0))
+        .insert_resource(Wave(1))
+        .insert_resource(Score(0))
+        .insert_resource(Paused(false))
+        .insert_resource(StepMode::default())
+        .insert_resource(Bombs(STARTING_BOMBS))
+        .insert_resource(SurvivalTime(0.))
+        .insert_resource(RunStats::default())
+        .insert_resource(StreakState::default())
+        .insert_resource(TimeScale(1.))
+        .insert_resource(Difficulty::default())
+        .insert_resource(GameMode::default())
+        .insert_resource(ColorblindMode(false))
+        .insert_resource(GameConfig::load())
+        .insert_resource(GameRng::new(args.seed))
+        .insert_resource(ScreenShake::default())
+        .insert_resource(ScreenFlash::default())
+        .insert_resource(CameraZoom(1.))
         .insert_resource(window_descriptor)
+        .add_event::<EnemyKilled>()
+        .add_event::<PlayerDied>()
         .add_plugins(DefaultPlugins)
+        .add_state(AppState::MainMenu)
         .add_plugin(PlayerPlugin)
         .add_plugin(EnemyPlugin)
+        .add_plugin(BossPlugin)
+        .add_plugin(HudPlugin)
+        .add_plugin(MenuPlugin)
+        .add_plugin(SettingsPlugin)
+        .add_plugin(GameOverPlugin)
+        .add_plugin(MusicPlugin)
+        .add_plugin(PowerUpPlugin)
+        .add_plugin(MissilePlugin)
+        .add_plugin(RumblePlugin)
+        .add_plugin(DebugPlugin)
         .add_startup_system(setup.system())
-        .add_system(player_laser_hit_enemy.system())
-        .add_system(enemy_laser_hit_player.system())
+        .add_startup_system_to_stage(StartupStage::PostStartup, spawn_background.system())
+        .add_system(update_window_size.system())
+        .add_system(toggle_fullscreen.system())
+        .add_system(camera_shake.system())
+        .add_system(dynamic_zoom.system())
+        .add_system(screen_flash.system())
+        .add_system(scroll_background.system())
+        // unpause around the whole update stage so every gameplay system runs once
+        .add_system_to_stage(CoreStage::PreUpdate, begin_frame_step.system().after(InputSystem))
+        .add_system_to_stage(CoreStage::PostUpdate, end_frame_step.system())
+        .add_system_set(
+            SystemSet::on_enter(AppState::InGame)
+            .with_system(reseed_rng.system())
+            .with_system(reset_survival_time.system())
+            .with_system(reset_run_stats.system())
+        )
+        .add_system_set(
+            SystemSet::on_update(AppState::InGame)
+            .with_system(toggle_pause.system())
+            .with_system(use_bomb.system())
+            .with_system(player_laser_hit_enemy.system())
+            .with_system(laser_vs_laser.system())
+            .with_system(enemy_laser_hit_player.system())
+            .with_system(enemy_body_hit_player.system())
+            .with_system(graze_detection.system())
+            .with_system(survival_score.system())
+            .with_system(track_run_time.system())
+            .with_system(despawn_out_of_bounds.system())
+        )
+        // the title screen demo shoots it out too, without any of it counting
+        .add_system_set(
+            SystemSet::on_update(AppState::MainMenu)
+            .with_system(demo_laser_hit_enemy.system())
+            .with_system(laser_vs_laser.system())
+            .with_system(despawn_out_of_bounds.system())
+        )
+        // outside the InGame set so the last death still plays out on the game over screen
+        .add_system(score_enemy_killed.system())
+        .add_system(enemy_killed_effects.system())
+        .add_system(player_died_effects.system())
+        .add_system(fade_muzzle_flashes.system())
         .add_system(explosion_to_spawn.system())
         .add_system(animate_explosion.system())
         .run();
 }
 
 
+/// Piped after a `FixedTimestep` so timed system sets only run while in game.
+fn in_game(In(should_run): In<ShouldRun>, app_state: Res<State<AppState>>) -> ShouldRun {
+    if app_state.current() == &AppState::InGame {
+        should_run
+    } else {
+        ShouldRun::No
+    }
+}
+
+/// Like `in_game`, but also lets the title-screen demo run.
+fn in_game_or_menu(In(should_run): In<ShouldRun>, app_state: Res<State<AppState>>) -> ShouldRun {
+    match app_state.current() {
+        AppState::InGame | AppState::MainMenu => should_run,
+        _ => ShouldRun::No
+    }
+}
+
 fn setup(
     mut commands: Commands,
     asset_server: Res<AssetServer>,
     mut texture_atlases: ResMut<Assets<TextureAtlas>>,
     mut materials: ResMut<Assets<ColorMaterial>>,
-    mut windows: ResMut<Windows>
+    mut windows: ResMut<Windows>,
+    config: Res<GameConfig>
 ) {
     let window = windows.get_primary_mut().unwrap();
     // camera
-    commands.spawn_bundle(OrthographicCameraBundle::new_2d());
+    let camera = OrthographicCameraBundle::new_2d();
+    // brings the HUD sprites back down to world depths, in front of the camera
+    let hud_depth = -camera.transform.translation.z;
+    commands
+        .spawn_bundle(camera)
+        .insert(MainCamera)
+        .with_children(|camera| {
+            camera
+                .spawn_bundle((Transform::from_xyz(0., 0., hud_depth), GlobalTransform::identity()))
+                .insert(HudRoot);
+        });
+    commands.spawn_bundle(UiCameraBundle::default());
+    // over everything in the world, sized to the window by screen_flash
+    commands.spawn_bundle(SpriteBundle {
+        material: materials.add(Color::NONE.into()),
+        transform: Transform::from_xyz(0., 0., 900.),
+        visible: Visible {
+            is_visible: false,
+            is_transparent: true
+        },
+        ..Default::default()
+    })
+    .insert(ScreenFlashOverlay);
     
     let texture_handle = asset_server.load(EXPLOSION_SHEET);
-    let texture_atlas = TextureAtlas::from_grid(texture_handle, Vec2::new(64.0, 64.0), 4, 4);
+    let texture_atlas = TextureAtlas::from_grid(
+        texture_handle,
+        Vec2::new(config.explosion_frame_width, config.explosion_frame_height),
+        config.explosion_columns,
+        config.explosion_rows
+    );
+    let enemy_atlas = TextureAtlas::from_grid(
+        asset_server.load(ENEMY_SHEET),
+        Vec2::new(ENEMY_FRAME_WIDTH, ENEMY_FRAME_HEIGHT),
+        ENEMY_FRAMES,
+        1
+    );
+    let thruster_atlas = TextureAtlas::from_grid(
+        asset_server.load(THRUSTER_SHEET),
+        Vec2::new(THRUSTER_FRAME_WIDTH, THRUSTER_FRAME_HEIGHT),
+        THRUSTER_FRAMES,
+        1
+    );
 
     commands.insert_resource(Materials {
         player: materials.add(asset_server.load(PLAYER_SPRITE).into()),
+        player_two: materials.add(asset_server.load(PLAYER_TWO_SPRITE).into()),
         player_laser: materials.add(asset_server.load(PLAYER_LASER_SPRITE).into()),
+        laser_trail: (1..=LASER_TRAIL_STEPS)
+            .map(|step| {
+                let alpha = 0.6 * (1. - (step - 1) as f32 / LASER_TRAIL_STEPS as f32);
+                materials.add(ColorMaterial::modulated_texture(asset_server.load(PLAYER_LASER_SPRITE), Color::rgba(1., 1., 1., alpha)))
+            })
+            .collect(),
+        muzzle_flash: (1..=MUZZLE_FLASH_STEPS)
+            .map(|step| {
+                let alpha = 1. - (step - 1) as f32 / MUZZLE_FLASH_STEPS as f32;
+                materials.add(ColorMaterial::modulated_texture(asset_server.load(MUZZLE_FLASH_SPRITE), Color::rgba(1., 1., 1., alpha)))
+            })
+            .collect(),
         enemy_laser: materials.add(asset_server.load(ENEMY_LASER_SPRITE).into()),
-        enemy: materials.add(asset_server.load(ENEMY_SPRITE).into()),
-        explosion: texture_atlases.add(texture_atlas)
+        player_laser_colorblind: materials.add(ColorMaterial::modulated_texture(asset_server.load(PLAYER_LASER_MONO_SPRITE), Color::CYAN)),
+        enemy_laser_colorblind: materials.add(ColorMaterial::modulated_texture(asset_server.load(ENEMY_LASER_MONO_SPRITE), Color::YELLOW)),
+        enemies: texture_atlases.add(enemy_atlas),
+        weak_point: materials.add(asset_server.load(WEAK_POINT_SPRITE).into()),
+        explosion: texture_atlases.add(texture_atlas),
+        thruster: texture_atlases.add(thruster_atlas),
+        powerup_speed: materials.add(Color::rgb(1., 0.85, 0.1).into()),
+        powerup_shield: materials.add(Color::rgb(0.45, 0.75, 1.).into()),
+        powerup_spread: materials.add(Color::rgb(0.9, 0.3, 0.9).into()),
+        powerup_piercing: materials.add(Color::rgb(1., 0.5, 0.1).into()),
+        powerup_bullet_time: materials.add(Color::rgb(0.4, 1., 0.6).into()),
+        shield: materials.add(asset_server.load(SHIELD_SPRITE).into()),
+        missile: materials.add(Color::rgb(1., 0.6, 0.2).into()),
+        spawn_warning: materials.add(asset_server.load(WARNING_SPRITE).into()),
+        background: materials.add(asset_server.load(BACKGROUND_SPRITE).into())
     });
     commands.insert_resource(WindowSize {
         width: window.width(),
         height: window.height()
     });
+    commands.insert_resource(HighScore::load());
+    commands.insert_resource(DailyBest::load());
+}
+
+
+fn spawn_background(mut commands: Commands, materials: Res<Materials>) {
+    // just above the camera's near plane, under the lasers at 0
+    for tile in 0..2 {
+        commands.spawn_bundle(SpriteBundle {
+            material: materials.background.clone(),
+            sprite: Sprite::new(Vec2::new(BACKGROUND_SIZE, BACKGROUND_SIZE)),
+            transform: Transform::from_xyz(0., tile as f32 * BACKGROUND_SIZE, -0.05),
+            ..Default::default()
+        })
+        .insert(Background);
+    }
+}
+
+fn reseed_rng(mut rng: ResMut<GameRng>, game_mode: Res<GameMode>, continuing: Res<Continuing>) {
+    if continuing.0 {
+        return;
+    }
+    if *game_mode == GameMode::DailyChallenge {
+        rng.reseed_daily(today());
+    } else {
+        rng.reseed();
+    }
+}
+
+fn reset_survival_time(mut survival_time: ResMut<SurvivalTime>, continuing: Res<Continuing>) {
+    if continuing.0 {
+        return;
+    }
+    survival_time.0 = 0.;
+}
+
+fn reset_run_stats(mut run_stats: ResMut<RunStats>, continuing: Res<Continuing>) {
+    if continuing.0 {
+        return;
+    }
+    *run_stats = RunStats::default();
+}
+
+fn update_window_size(mut resize_events: EventReader<WindowResized>, mut window_size: ResMut<WindowSize>) {
+    for event in resize_events.iter() {
+        if event.id == WindowId::primary() {
+            window_size.width = event.width;
+            window_size.height = event.height;
+        }
+    }
+}
+
+// the new size arrives as a WindowResized event, which keeps WindowSize in sync
+fn toggle_fullscreen(keyboard_input: Res<Input<KeyCode>>, mut windows: ResMut<Windows>) {
+    if keyboard_input.just_pressed(KeyCode::F11) {
+        if let Some(window) = windows.get_primary_mut() {
+            let mode = match window.mode() {
+                WindowMode::Windowed => WindowMode::BorderlessFullscreen,
+                _ => WindowMode::Windowed
+            };
+            window.set_mode(mode);
+        }
+    }
+}
+
+fn camera_shake(
+    time: Res<Time>,
+    mut shake: ResMut<ScreenShake>,
+    mut query: Query<&mut Transform, With<MainCamera>>
+) {
+    shake.timer.tick(time.delta());
+    if let Ok(mut tf) = query.single_mut() {
+        if shake.timer.finished() {
+            tf.translation.x = 0.;
+            tf.translation.y = 0.;
+        } else {
+            // jitter fades out over the shake duration
+            let magnitude = shake.magnitude * shake.timer.percent_left();
+            // only looks, so it stays off GameRng and leaves the seeded run alone
+            let mut rng = thread_rng();
+            tf.translation.x = rng.gen_range(-1.0..1.0) * magnitude;
+            tf.translation.y = rng.gen_range(-1.0..1.0) * magnitude;
+        }
+    }
+}
+
+fn dynamic_zoom(
+    time: Res<Time>,
+    active_enemies: Res<ActiveEnemies>,
+    mut zoom: ResMut<CameraZoom>,
+    mut query: Query<(&mut Camera, &mut OrthographicProjection), With<MainCamera>>
+) {
+    let crowd = (active_enemies.0 as f32 / ZOOM_FULL_ENEMIES).min(1.);
+    let target = 1. + (MAX_ZOOM - 1.) * crowd;
+    zoom.0 += (target - zoom.0) * (ZOOM_RATE * time.delta_seconds()).min(1.);
+    if let Ok((mut camera, mut projection)) = query.single_mut() {
+        projection.scale = zoom.0;
+        // bevy only rebuilds the matrix on a resize
+        camera.projection_matrix = projection.get_projection_matrix();
+    }
+}
+
+fn screen_flash(
+    time: Res<Time>,
+    window_size: Res<WindowSize>,
+    zoom: Res<CameraZoom>,
+    mut flash: ResMut<ScreenFlash>,
+    mut materials: ResMut<Assets<ColorMaterial>>,
+    mut query: Query<(&Handle<ColorMaterial>, &mut Sprite, &mut Visible), With<ScreenFlashOverlay>>
+) {
+    flash.timer.tick(time.delta());
+    for (handle, mut sprite, mut visible) in query.iter_mut() {
+        let active = !flash.timer.finished();
+        if visible.is_visible != active {
+            visible.is_visible = active;
+        }
+        if !active {
+            continue;
+        }
+        // big enough to still cover the window while the camera shakes
+        let margin = 2. * SHAKE_MAGNITUDE;
+        sprite.size = Vec2::new(window_size.width + margin, window_size.height + margin) * zoom.0;
+        if let Some(material) = materials.get_mut(handle) {
+            let mut color = flash.color;
+            color.set_a(flash.color.a() * flash.timer.percent_left());
+            material.color = color;
+        }
+    }
+}
+
+fn scroll_background(
+    time: Res<Time>,
+    paused: Res<Paused>,
+    window_size: Res<WindowSize>,
+    mut query: Query<&mut Transform, With<Background>>
+) {
+    // each tile covers the whole view zoomed all the way out, so two always fill it
+    let scale = (window_size.width.max(window_size.height) * MAX_ZOOM / BACKGROUND_SIZE).max(1.);
+    let tile_height = BACKGROUND_SIZE * scale;
+    if window_size.is_changed() {
+        // restack on a resize so the tiles don't gap or overlap at the new scale
+        let mut tiles: Vec<_> = query.iter_mut().collect();
+        tiles.sort_by(|a, b| a.translation.y.partial_cmp(&b.translation.y).unwrap());
+        let bottom = tiles.first().map_or(0., |tf| tf.translation.y);
+        for (index, tf) in tiles.iter_mut().enumerate() {
+            tf.scale = Vec3::new(scale, scale, 1.);
+            tf.translation.y = bottom + index as f32 * tile_height;
+        }
+    }
+    if paused.0 {
+        return;
+    }
+    for mut tf in query.iter_mut() {
+        tf.translation.y -= BACKGROUND_SPEED * time.delta_seconds();
+        // once its top edge drops out of view it goes back on top of the other tile
+        if tf.translation.y + tile_height / 2. < -window_size.height * MAX_ZOOM / 2. {
+            tf.translation.y += 2. * tile_height;
+        }
+    }
+}
+
+fn toggle_pause(keyboard_input: Res<Input<KeyCode>>, mut paused: ResMut<Paused>) {
+    if keyboard_input.just_pressed(KeyCode::Escape) || keyboard_input.just_pressed(KeyCode::P) {
+        paused.0 = !paused.0;
+    }
+}
+
+fn begin_frame_step(
+    keyboard_input: Res<Input<KeyCode>>,
+    app_state: Res<State<AppState>>,
+    mut paused: ResMut<Paused>,
+    mut step_mode: ResMut<StepMode>
+) {
+    if paused.0 && *app_state.current() == AppState::InGame && keyboard_input.just_pressed(KeyCode::Period) {
+        paused.0 = false;
+        step_mode.stepping = true;
+    }
+}
+
+fn end_frame_step(mut paused: ResMut<Paused>, mut step_mode: ResMut<StepMode>) {
+    if step_mode.stepping {
+        paused.0 = true;
+        step_mode.stepping = false;
+    }
+}
+
+// clears every regular enemy and enemy laser on screen
+fn use_bomb(
+    mut commands: Commands,
+    keyboard_input: Res<Input<KeyCode>>,
+    paused: Res<Paused>,
+    game_mode: Res<GameMode>,
+    mut bombs: ResMut<Bombs>,
+    mut active_enemies: ResMut<ActiveEnemies>,
+    enemy_query: Query<(Entity, &Transform), (With<Enemy>, Without<Boss>)>,
+    laser_query: Query<Entity, (With<Laser>, With<FromEnemy>)>
+) {
+    if paused.0 || !game_mode.can_fire() || bombs.0 == 0 || !keyboard_input.just_pressed(KeyCode::B) {
+        return;
+    }
+
+    // bosses shrug the bomb off
+    for (enemy_entity, enemy_tf) in enemy_query.iter() {
+        commands.entity(enemy_entity).despawn();
+        commands
+            .spawn()
+            .insert(ExplosionToSpawn::small(enemy_tf.translation));
+        active_enemies.0 = active_enemies.0.saturating_sub(1);
+    }
+    for laser_entity in laser_query.iter() {
+        commands.entity(laser_entity).despawn();
+    }
+
+    bombs.0 -= 1;
+}
+
+fn spawn_muzzle_flash(commands: &mut Commands, materials: &Materials, translation: Vec3) {
+    commands.spawn_bundle(SpriteBundle {
+        material: materials.muzzle_flash[0].clone(),
+        // over the laser it lit up
+        transform: Transform::from_xyz(translation.x, translation.y, translation.z + 1.),
+        ..Default::default()
+    })
+    .insert(MuzzleFlash(Timer::from_seconds(MUZZLE_FLASH_TIME, false)));
+}
+
+// steps through the pre-made materials like the laser trail does
+fn fade_muzzle_flashes(
+    mut commands: Commands,
+    materials: Res<Materials>,
+    time: Res<Time>,
+    paused: Res<Paused>,
+    mut query: Query<(Entity, &mut MuzzleFlash, &mut Handle<ColorMaterial>)>
+) {
+    if paused.0 {
+        return;
+    }
+    let steps = materials.muzzle_flash.len();
+    for (entity, mut flash, mut material) in query.iter_mut() {
+        flash.0.tick(time.delta());
+        if flash.0.finished() {
+            commands.entity(entity).despawn();
+            continue;
+        }
+        let step = ((flash.0.percent() * steps as f32) as usize).min(steps - 1);
+        if *material != materials.muzzle_flash[step] {
+            *material = materials.muzzle_flash[step].clone();
+        }
+    }
+}
+
+/// Overlap test on the sprites' on-screen boxes, given their unscaled sizes.
+/// Scale is taken as absolute since flipped sprites (enemy lasers) carry a
+/// negative scale.
+fn aabb_collides(a_tf: &Transform, a_size: Vec2, b_tf: &Transform, b_size: Vec2) -> bool {
+    let a_size = a_size * Vec2::from(a_tf.scale.abs());
+    let b_size = b_size * Vec2::from(b_tf.scale.abs());
+    collide(a_tf.translation, a_size, b_tf.translation, b_size).is_some()
+}
+
+fn half_width(tf: &Transform, size: Vec2) -> f32 {
+    size.x * tf.scale.x.abs() / 2.
+}
+
+/// The unscaled size of the frame a sprite sheet entity shows, what
+/// `Sprite::size` is for a plain sprite.
+fn frame_size(texture_atlases: &Assets<TextureAtlas>, atlas: &Handle<TextureAtlas>, sprite: &TextureAtlasSprite) -> Vec2 {
+    texture_atlases
+        .get(atlas)
+        .and_then(|atlas| atlas.textures.get(sprite.index as usize))
+        .map_or(Vec2::ZERO, |rect| rect.max - rect.min)
+}
+
+/// Applies `damage` to an enemy. When that kills it, the enemy is removed,
+/// an `EnemyKilled` is sent for the score and effects, and this returns true.
+/// A berserker that survives the hit speeds up.
+fn damage_enemy(
+    commands: &mut Commands,
+    active_enemies: &mut ActiveEnemies,
+    enemy_killed: &mut EventWriter<EnemyKilled>,
+    berserk_query: &mut Query<(&Berserk, &mut Speed)>,
+    enemy_entity: Entity,
+    enemy_tf: &Transform,
+    health: &mut Health,
+    boss: Option<&Boss>,
+    kind: Option<&EnemyKind>,
+    damage: u32
+) -> bool {
+    // already destroyed this frame, its despawn just hasn't applied yet
+    if health.current == 0 {
+        return false;
+    }
+    health.current = health.current.saturating_sub(damage);
+    if health.current > 0 {
+        // still standing, and angrier for it
+        if let Ok((berserk, mut speed)) = berserk_query.get_mut(enemy_entity) {
+            *speed = berserk.speed(health);
+        }
+        return false;
+    }
+
+    // remove the enemy, with the boss's weak point if it has one
+    commands.entity(enemy_entity).despawn_recursive();
+    active_enemies.0 = active_enemies.0.saturating_sub(1);
+    enemy_killed.send(EnemyKilled {
+        position: enemy_tf.translation,
+        kind: kind.copied(),
+        boss: boss.is_some(),
+        max_health: health.max
+    });
+
+    true
 }
 
+fn score_enemy_killed(
+    mut commands: Commands,
+    mut score: ResMut<Score>,
+    mut run_stats: ResMut<RunStats>,
+    mut events: EventReader<EnemyKilled>
+) {
+    for killed in events.iter() {
+        run_stats.enemies_killed += 1;
+        // tougher enemies are worth more
+        let mut points = ENEMY_POINTS * killed.max_health;
+        if killed.boss {
+            points += BOSS_KILL_BONUS;
+        }
+        score.0 += points;
+        commands
+            .spawn()
+            .insert(PointsToSpawn { position: killed.position, points });
+    }
+}
+
+fn enemy_killed_effects(mut commands: Commands, mut rng: ResMut<GameRng>, mut events: EventReader<EnemyKilled>) {
+    for killed in events.iter() {
+        // spawn explosion to spawn
+        let explosion = match (killed.boss, killed.max_health) {
+            (true, _) => ExplosionToSpawn::large(killed.position).with_flash(BOSS_FLASH),
+            (false, 1) => ExplosionToSpawn::small(killed.position),
+            (false, _) => ExplosionToSpawn::new(killed.position)
+        };
+        commands
+            .spawn()
+            .insert(explosion);
+        if killed.boss {
+            rumble(&mut commands, RumbleToPlay::boss_kill());
+            // a ring of extra blasts for the big one
+            for &(dx, dy) in &[(-40., -20.), (40., -20.), (-25., 25.), (25., 25.)] {
+                commands
+                    .spawn()
+                    .insert(ExplosionToSpawn::new(killed.position + Vec3::new(dx, dy, 0.)));
+            }
+        }
+        if killed.kind == Some(EnemyKind::Splitter) {
+            commands.spawn().insert(SplitToSpawn(killed.position));
+        }
+        // small chance to drop a power-up
+        if rng.rng.gen_bool(POWERUP_DROP_CHANCE) {
+            commands
+                .spawn()
+                .insert(PowerUpToSpawn(killed.position));
+        }
+    }
+}
+
+fn player_died_effects(
+    mut commands: Commands,
+    mut shake: ResMut<ScreenShake>,
+    mut streak: ResMut<StreakState>,
+    mut events: EventReader<PlayerDied>
+) {
+    for died in events.iter() {
+        // show ti fire
+        commands.spawn().insert(ExplosionToSpawn::new(died.position).with_flash(PLAYER_FLASH));
+        shake.start(SHAKE_MAGNITUDE);
+        rumble(&mut commands, RumbleToPlay::death());
+        streak.kills_since_death = 0;
+    }
+}
 
 fn player_laser_hit_enemy(
     mut commands: Commands,
-    mut laser_query: Query<(Entity, &Transform, &Sprite, (With<Laser>, With<FromPlayer>))>,
-    mut enemy_query: Query<(Entity, &Transform, &Sprite, With<Enemy>)>,
-    mut active_enemies: ResMut<ActiveEnemies>
+    mut laser_query: Query<(Entity, &Transform, &Sprite, Option<&ChargedLaser>, Option<&mut Piercing>, (With<Laser>, With<FromPlayer>))>,
+    mut enemy_query: Query<(Entity, &Transform, &TextureAtlasSprite, &Handle<TextureAtlas>, &mut Health, Option<&Boss>, Option<&EnemyKind>, Option<&Shielded>), With<Enemy>>,
+    texture_atlases: Res<Assets<TextureAtlas>>,
+    weak_point_query: Query<(&Parent, &WeakPoint)>,
+    mut berserk_query: Query<(&Berserk, &mut Speed)>,
+    mut active_enemies: ResMut<ActiveEnemies>,
+    mut run_stats: ResMut<RunStats>,
+    mut enemy_killed: EventWriter<EnemyKilled>,
+    mut streak: ResMut<StreakState>
 ) {
+    // broad phase: enemies sorted by x so each laser only checks the ones
+    // whose horizontal extent could reach it
+    let mut enemies_by_x: Vec<(f32, Entity)> = Vec::new();
+    let mut max_half_width: f32 = 0.;
+    for (enemy_entity, enemy_tf, enemy_sprite, enemy_atlas, ..) in enemy_query.iter_mut() {
+        enemies_by_x.push((enemy_tf.translation.x, enemy_entity));
+        max_half_width = max_half_width.max(half_width(enemy_tf, frame_size(&texture_atlases, enemy_atlas, enemy_sprite)));
+    }
+    enemies_by_x.sort_by(|a, b| a.0.partial_cmp(&b.0).unwrap());
+
     let mut enemies_blasted: HashSet<Entity> = HashSet::new();
-    for (laser_entity, laser_tf, laser_sprite, _) in laser_query.iter_mut() {
-        for (enemy_entity, enemy_tf, enemy_sprite, _) in enemy_query.iter_mut() {
-            let laser_scale = Vec2::from(laser_tf.scale);
-            let enemy_scale = Vec2::from(enemy_tf.scale);
-
-            let collision = collide(
-                laser_tf.translation,
-                laser_sprite.size * laser_scale,
-                enemy_tf.translation,
-                enemy_sprite.size * enemy_scale
-            );
-            
-            if let Some(_) = collision {
-                if enemies_blasted.get(&enemy_entity).is_none() {
-                   // remove the enemy
-                    commands.entity(enemy_entity).despawn();
-                    active_enemies.0 -= 1;
-                    // spawn explosion to spawn
-                    commands
-                        .spawn()
-                        .insert(ExplosionToSpawn(enemy_tf.translation.clone()));
-                    
+    for (laser_entity, laser_tf, laser_sprite, charged, mut piercing, _) in laser_query.iter_mut() {
+        let damage = if charged.is_some() { CHARGED_LASER_DAMAGE } else { 1 };
+        let reach = half_width(laser_tf, laser_sprite.size) + max_half_width;
+        let (min_x, max_x) = (laser_tf.translation.x - reach, laser_tf.translation.x + reach);
+        let first = enemies_by_x.partition_point(|&(x, _)| x < min_x);
+        // a piercing laser counts as a hit the first time it goes through something
+        let hit_before = piercing.as_ref().map_or(false, |piercing| !piercing.0.is_empty());
+        let mut hit = false;
+        for &(_, enemy_entity) in enemies_by_x[first..].iter().take_while(|&&(x, _)| x <= max_x) {
+            let (_, enemy_tf, enemy_sprite, enemy_atlas, mut health, boss, kind, shielded) = enemy_query.get_mut(enemy_entity).unwrap();
+            if aabb_collides(laser_tf, laser_sprite.size, enemy_tf, frame_size(&texture_atlases, enemy_atlas, enemy_sprite)) {
+                // a guardian's shield stops even piercing lasers
+                if shielded.is_some() {
+                    commands.entity(laser_entity).despawn();
+                    commands.spawn().insert(ExplosionToSpawn::spark(laser_tf.translation));
+                    break;
+                }
+                // a piercing laser only damages each enemy once on its way through
+                if let Some(piercing) = piercing.as_mut() {
+                    if !piercing.0.insert(enemy_entity) {
+                        continue;
+                    }
+                }
+                hit = true;
+
+                // the boss only takes full damage through its weak point
+                let damage = if weak_point_query.iter().any(|(parent, weak_point)| parent.0 == enemy_entity && weak_point.contains(enemy_tf, laser_tf.translation)) {
+                    damage * WEAK_POINT_DAMAGE
+                } else {
+                    damage
+                };
+                if enemies_blasted.get(&enemy_entity).is_none()
+                    && damage_enemy(&mut commands, &mut active_enemies, &mut enemy_killed, &mut berserk_query, enemy_entity, enemy_tf, &mut health, boss, kind, damage) {
                     enemies_blasted.insert(enemy_entity);
+                    streak.kills_since_death += 1;
                 }
                 
-                // remove the laser
-                commands.entity(laser_entity).despawn();
+                // remove the laser, unless it keeps going
+                if piercing.is_none() {
+                    commands.entity(laser_entity).despawn();
+                }
             }
         }
+        if hit && !hit_before {
+            run_stats.shots_hit += 1;
+        }
+    }
+}
+
+// the demo ship's lasers knock the title screen enemies out in one hit, no
+// score, drops or splits, so nothing carries over into the game
+fn demo_laser_hit_enemy(
+    mut commands: Commands,
+    mut active_enemies: ResMut<ActiveEnemies>,
+    texture_atlases: Res<Assets<TextureAtlas>>,
+    laser_query: Query<(Entity, &Transform, &Sprite), (With<Laser>, With<FromPlayer>)>,
+    enemy_query: Query<(Entity, &Transform, &TextureAtlasSprite, &Handle<TextureAtlas>), With<Enemy>>
+) {
+    let mut destroyed: HashSet<Entity> = HashSet::new();
+    for (laser_entity, laser_tf, laser_sprite) in laser_query.iter() {
+        for (enemy_entity, enemy_tf, enemy_sprite, enemy_atlas) in enemy_query.iter() {
+            if destroyed.contains(&enemy_entity)
+                || !aabb_collides(laser_tf, laser_sprite.size, enemy_tf, frame_size(&texture_atlases, enemy_atlas, enemy_sprite)) {
+                continue;
+            }
+            commands.entity(laser_entity).despawn();
+            commands.entity(enemy_entity).despawn_recursive();
+            commands.spawn().insert(ExplosionToSpawn::small(enemy_tf.translation));
+            active_enemies.0 = active_enemies.0.saturating_sub(1);
+            destroyed.insert(enemy_entity);
+            break;
+        }
+    }
+}
+
+// player lasers shoot down enemy ones. Only reads the lasers and despawns
+// through commands, so it doesn't hold up the hit checks around it; a laser
+// that also hits an enemy this frame still lands that hit.
+fn laser_vs_laser(
+    mut commands: Commands,
+    player_laser_query: Query<(Entity, &Transform, &Sprite, Option<&ChargedLaser>, Option<&Piercing>), (With<Laser>, With<FromPlayer>)>,
+    enemy_laser_query: Query<(Entity, &Transform, &Sprite), (With<Laser>, With<FromEnemy>)>
+) {
+    let mut cancelled: HashSet<Entity> = HashSet::new();
+    for (player_laser, player_tf, player_sprite, charged, piercing) in player_laser_query.iter() {
+        for (enemy_laser, enemy_tf, enemy_sprite) in enemy_laser_query.iter() {
+            if cancelled.contains(&enemy_laser) || !aabb_collides(player_tf, player_sprite.size, enemy_tf, enemy_sprite.size) {
+                continue;
+            }
+            commands.entity(enemy_laser).despawn();
+            commands.spawn().insert(ExplosionToSpawn::spark(enemy_tf.translation));
+            cancelled.insert(enemy_laser);
+            // charged and piercing lasers keep going, a plain one is spent
+            if charged.is_none() && piercing.is_none() {
+                commands.entity(player_laser).despawn();
+                break;
+            }
+        }
+    }
+}
+
+/// Lands a hit on a player: an active shield soaks it up, otherwise the
+/// ship and a life are lost. Returns false when that ship already went down
+/// this frame, so the same death isn't counted twice.
+fn hit_player(
+    commands: &mut Commands,
+    player_entity: Entity,
+    player_id: PlayerId,
+    player_tf: &Transform,
+    shield: Option<&mut Shield>,
+    player_state: &mut PlayerStatte,
+    lives: &mut Lives,
+    app_state: &mut State<AppState>,
+    player_died: &mut EventWriter<PlayerDied>,
+    now: f64
+) -> bool {
+    if !player_state.is_on(player_id) {
+        return false;
+    }
+    // an active shield soaks up the hit instead
+    if let Some(shield) = shield.filter(|shield| shield.0 > 0) {
+        shield.0 -= 1;
+        if shield.0 == 0 {
+            commands.entity(player_entity).remove::<Shield>();
+        }
+        return true;
+    }
+
+    // remove the player from the view
+    // takes the thruster along with it
+    commands.entity(player_entity).despawn_recursive();
+    player_state.shot(player_id, now);
+    player_died.send(PlayerDied { position: player_tf.translation });
+    // lose a life, no respawn once they are all gone. In co-op the game
+    // goes on while the other ship is still flying or has lives left.
+    let player_lives = &mut lives.0[player_id.0 as usize];
+    *player_lives = player_lives.saturating_sub(1);
+    if lives.all_lost() {
+        // boss rush may have queued the victory screen on the same frame
+        let _ = app_state.set(AppState::GameOver);
     }
+    true
 }
 
 fn enemy_laser_hit_player(
     mut commands: Commands,
     mut player_state: ResMut<PlayerStatte>,
+    mut lives: ResMut<Lives>,
+    mut app_state: ResMut<State<AppState>>,
+    mut player_died: EventWriter<PlayerDied>,
     time: Res<Time>,
     laser_query: Query<(Entity, &Transform, &Sprite), (With<Laser>, With<FromEnemy>)>,
-    player_query: Query<(Entity, &Transform, &Sprite), With<Player>>
+    mut player_query: Query<(Entity, &PlayerId, &Transform, &Sprite, Option<&Invulnerable>, Option<&mut Shield>, &Dash), With<Player>>
 ) {
-    if let Ok((player_entity, player_tf, player_sprite)) = player_query.single() {
-        let player_size = player_sprite.size * Vec2::from(player_tf.scale.abs());
+    let now = time.seconds_since_startup();
+    // a laser is spent on the first ship it hits
+    let mut spent: HashSet<Entity> = HashSet::new();
+    for (player_entity, &player_id, player_tf, player_sprite, invulnerable, mut shield, dash) in player_query.iter_mut() {
+        // freshly respawned players can't be hit yet
+        if invulnerable.map_or(false, Invulnerable::active) {
+            continue;
+        }
+        // mid-dash lasers go straight through
+        if dash.dashing() {
+            continue;
+        }
+
         for (laser_entity, laser_tf, laser_sprite) in laser_query.iter() {
-            let laser_size = laser_sprite.size * Vec2::from(laser_tf.scale.abs());
-            let collision = collide(laser_tf.translation, laser_size, player_tf.translation, player_size);
-            if let Some(_) = collision {
-                // remove the player from the view
-                commands.entity(player_entity).despawn();
-                player_state.shot(time.seconds_since_startup());
-                //remove the laser
-                commands.entity(laser_entity).despawn();
-                // show ti fire
-                commands.spawn().insert(ExplosionToSpawn(player_tf.translation.clone()));
+            if spent.contains(&laser_entity) || !aabb_collides(laser_tf, laser_sprite.size, player_tf, player_sprite.size) {
+                continue;
+            }
+            let hit = hit_player(
+                &mut commands,
+                player_entity,
+                player_id,
+                player_tf,
+                shield.as_deref_mut(),
+                &mut player_state,
+                &mut lives,
+                &mut app_state,
+                &mut player_died,
+                now
+            );
+            if !hit {
+                break;
+            }
+            //remove the laser
+            commands.entity(laser_entity).despawn();
+            spent.insert(laser_entity);
+            if !player_state.is_on(player_id) {
+                break;
+            }
+        }
+    }
+}
+
+// running into an enemy is as deadly as its lasers. Regular enemies are wrecked
+// by the crash, the boss shrugs it off.
+fn enemy_body_hit_player(
+    mut commands: Commands,
+    mut player_state: ResMut<PlayerStatte>,
+    mut lives: ResMut<Lives>,
+    mut app_state: ResMut<State<AppState>>,
+    mut player_died: EventWriter<PlayerDied>,
+    mut active_enemies: ResMut<ActiveEnemies>,
+    mut enemy_killed: EventWriter<EnemyKilled>,
+    time: Res<Time>,
+    texture_atlases: Res<Assets<TextureAtlas>>,
+    mut enemy_query: Query<(Entity, &Transform, &TextureAtlasSprite, &Handle<TextureAtlas>, &mut Health, Option<&Boss>, Option<&EnemyKind>), With<Enemy>>,
+    mut berserk_query: Query<(&Berserk, &mut Speed)>,
+    mut player_query: Query<(Entity, &PlayerId, &Transform, &Sprite, Option<&Invulnerable>, Option<&mut Shield>), (With<Player>, Without<Enemy>)>
+) {
+    let now = time.seconds_since_startup();
+    for (player_entity, &player_id, player_tf, player_sprite, invulnerable, mut shield) in player_query.iter_mut() {
+        // the respawn window covers the body hits too, so an enemy diving
+        // onto the spawn point can't take the new ship straight away
+        if invulnerable.map_or(false, Invulnerable::active) {
+            continue;
+        }
+
+        for (enemy_entity, enemy_tf, enemy_sprite, enemy_atlas, mut health, boss, kind) in enemy_query.iter_mut() {
+            if health.current == 0 || !aabb_collides(enemy_tf, frame_size(&texture_atlases, enemy_atlas, enemy_sprite), player_tf, player_sprite.size) {
+                continue;
+            }
+            let hit = hit_player(
+                &mut commands,
+                player_entity,
+                player_id,
+                player_tf,
+                shield.as_deref_mut(),
+                &mut player_state,
+                &mut lives,
+                &mut app_state,
+                &mut player_died,
+                now
+            );
+            if !hit {
+                break;
             }
+            if boss.is_none() {
+                let damage = health.current;
+                damage_enemy(&mut commands, &mut active_enemies, &mut enemy_killed, &mut berserk_query, enemy_entity, enemy_tf, &mut health, boss, kind, damage);
+            }
+            if !player_state.is_on(player_id) {
+                break;
+            }
+        }
+    }
+}
+
+// an enemy laser inside the graze margin, pays out if it leaves without hitting
+struct Grazing;
+// an enemy laser that already paid out for a near miss
+struct Grazed;
+
+fn graze_detection(
+    mut commands: Commands,
+    mut score: ResMut<Score>,
+    laser_query: Query<(Entity, &Transform, &Sprite, Option<&Grazing>), (With<Laser>, With<FromEnemy>, Without<Grazed>)>,
+    player_query: Query<(&Transform, &Sprite, Option<&Invulnerable>), With<Player>>
+) {
+    // ships that can't be hit can't graze either
+    let ships: Vec<(Vec3, Vec2)> = player_query
+        .iter()
+        .filter(|(_, _, invulnerable)| invulnerable.map_or(true, |invulnerable| !invulnerable.active()))
+        .map(|(tf, sprite, _)| (tf.translation, sprite.size * Vec2::from(tf.scale.abs()) / 2.))
+        .collect();
+    if ships.is_empty() {
+        return;
+    }
+    for (laser_entity, laser_tf, laser_sprite, grazing) in laser_query.iter() {
+        let laser_half = laser_sprite.size * Vec2::from(laser_tf.scale.abs()) / 2.;
+        // close to either ship in co-op counts
+        let near = ships.iter().any(|&(player_translation, player_half)| {
+            let gap = (laser_tf.translation - player_translation).truncate().abs() - player_half - laser_half;
+            gap.x < GRAZE_MARGIN && gap.y < GRAZE_MARGIN
+        });
+        match (near, grazing.is_some()) {
+            (true, false) => {
+                commands.entity(laser_entity).insert(Grazing);
+            }
+            // it slipped past, a hit would have despawned it in enemy_laser_hit_player
+            (false, true) => {
+                score.0 += GRAZE_POINTS;
+                commands.entity(laser_entity).remove::<Grazing>().insert(Grazed);
+                commands.spawn().insert(ExplosionToSpawn::spark(laser_tf.translation));
+            }
+            _ => {}
+        }
+    }
+}
+
+// pacifist runs score by the second while a ship is out, the whole points
+// are paid as the clock crosses them so the rate doesn't depend on frame time
+fn survival_score(
+    time: Res<Time>,
+    paused: Res<Paused>,
+    game_mode: Res<GameMode>,
+    mut score: ResMut<Score>,
+    mut survival_time: ResMut<SurvivalTime>,
+    player_query: Query<(), With<Player>>
+) {
+    if paused.0 || *game_mode != GameMode::Pacifist || player_query.iter().next().is_none() {
+        return;
+    }
+    let before = survival_time.0;
+    survival_time.0 += time.delta_seconds();
+    score.0 += (survival_time.0 * SURVIVAL_POINTS_PER_SECOND) as u32 - (before * SURVIVAL_POINTS_PER_SECOND) as u32;
+}
+
+fn track_run_time(time: Res<Time>, paused: Res<Paused>, mut run_stats: ResMut<RunStats>) {
+    if !paused.0 {
+        run_stats.time += time.delta_seconds();
+    }
+}
+
+// anything else despawn_out_of_bounds should clear once it leaves the screen
+pub struct DespawnOffscreen;
+
+fn despawn_out_of_bounds(
+    mut commands: Commands,
+    window_size: Res<WindowSize>,
+    query: Query<(Entity, &Transform), Or<(With<Laser>, With<PowerUp>, With<DespawnOffscreen>)>>
+) {
+    let (x_limit, y_limit) = (window_size.width / 2. + OFFSCREEN_MARGIN, window_size.height / 2. + OFFSCREEN_MARGIN);
+    for (entity, tf) in query.iter() {
+        if tf.translation.x.abs() > x_limit || tf.translation.y.abs() > y_limit {
+            commands.entity(entity).despawn();
         }
     }
 }
 
 fn explosion_to_spawn(
     mut commands: Commands,
+    mut flash: ResMut<ScreenFlash>,
     query: Query<(Entity, &ExplosionToSpawn)>,
     materials: Res<Materials>
 ) {
     for (explosion_entity, explosion_to_spawn) in query.iter() {
+        if let Some(color) = explosion_to_spawn.flash {
+            flash.start(color);
+        }
         commands
             .spawn_bundle(SpriteSheetBundle {
                 texture_atlas: materials.explosion.clone(),
                 transform: Transform {
-                    translation: explosion_to_spawn.0,
+                    translation: explosion_to_spawn.position,
+                    scale: Vec3::new(explosion_to_spawn.scale, explosion_to_spawn.scale, 1.),
                     ..Default::default()
                 },
                 ..Default::default()
             })
             .insert(Explosion)
-            .insert(Timer::from_seconds(0.05, true));
+            .insert(Timer::from_seconds(explosion_to_spawn.frame_time, true));
 
         commands.entity(explosion_entity).despawn();
     }
@@ -179,6 +1566,7 @@ fn explosion_to_spawn(
 fn animate_explosion(
     mut commands: Commands,
     time: Res<Time>,
+    paused: Res<Paused>,
     texture_atlases: Res<Assets<TextureAtlas>>,
     mut query: Query<(
         Entity,
@@ -188,14 +1576,228 @@ fn animate_explosion(
         With<Explosion>
     )>
 ) {
+    if paused.0 {
+        return;
+    }
     for (entity, mut timer, mut sprite, texture_atlas_handle, _) in query.iter_mut() {
         timer.tick(time.delta());
         if timer.finished() {
-            let texture_atlas = texture_atlases.get(texture_atlas_handle).unwrap();
-            sprite.index += 1;
-            if sprite.index == texture_atlas.textures.len() as u32 {
+            let frames = match texture_atlases.get(texture_atlas_handle) {
+                Some(texture_atlas) => texture_atlas.textures.len() as u32,
+                None => continue
+            };
+            // a long frame can cover several sheet frames, skip ahead by all of them
+            sprite.index += timer.times_finished();
+            if sprite.index >= frames {
+                sprite.index = frames.saturating_sub(1);
                 commands.entity(entity).despawn();
             }
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, Instant};
+
+    use bevy::{asset::AssetPlugin, ecs::schedule::SystemDescriptor, sprite::Rect};
+
+    use super::*;
+
+    // no window or renderer, just the clock and somewhere to keep sprite sheets
+    fn headless_app() -> AppBuilder {
+        let mut app = App::build();
+        app.add_plugins(MinimalPlugins)
+            .add_plugin(AssetPlugin)
+            .add_asset::<TextureAtlas>()
+            .insert_resource(Paused(false));
+        app
+    }
+
+    fn sprite_sheet(app: &mut AppBuilder, frame: Vec2, frames: usize) -> Handle<TextureAtlas> {
+        let mut atlas = TextureAtlas::new_empty(Handle::default(), Vec2::new(frame.x * frames as f32, frame.y));
+        for i in 0..frames {
+            let x = frame.x * i as f32;
+            atlas.add_texture(Rect { min: Vec2::new(x, 0.), max: Vec2::new(x + frame.x, frame.y) });
+        }
+        app.world_mut().get_resource_mut::<Assets<TextureAtlas>>().unwrap().add(atlas)
+    }
+
+    const LASERS: usize = 500;
+    const ENEMIES: usize = 100;
+
+    // lasers and enemies scattered over the screen the same way every time
+    fn collision_app(hit_system: impl Into<SystemDescriptor>) -> App {
+        let mut app = headless_app();
+        app.add_event::<EnemyKilled>()
+            .insert_resource(ActiveEnemies(ENEMIES as u32))
+            .init_resource::<RunStats>()
+            .init_resource::<StreakState>()
+            .add_system(hit_system);
+        // an empty first frame, so the timing leaves out setting up the schedule
+        app.app.update();
+        let sheet = sprite_sheet(&mut app, Vec2::new(ENEMY_FRAME_WIDTH, ENEMY_FRAME_HEIGHT), ENEMY_FRAMES);
+        let mut rng = StdRng::seed_from_u64(0);
+        let world = app.world_mut();
+        for _ in 0..ENEMIES {
+            let translation = Vec3::new(rng.gen_range(-300.0..300.), rng.gen_range(0.0..330.), 10.);
+            let tf = Transform { translation, scale: Vec3::new(SCALE, SCALE, 1.), ..Default::default() };
+            world.spawn().insert_bundle((Enemy, tf, TextureAtlasSprite::default(), sheet.clone(), Health::new(3)));
+        }
+        for _ in 0..LASERS {
+            let translation = Vec3::new(rng.gen_range(-300.0..300.), rng.gen_range(-330.0..330.), 0.);
+            let tf = Transform { translation, scale: Vec3::new(SCALE, SCALE, 1.), ..Default::default() };
+            world.spawn().insert_bundle((Laser, FromPlayer, tf, Sprite::new(Vec2::new(9., 54.))));
+        }
+        app.app
+    }
+
+    // one frame of the collision system over the whole scene
+    fn time_hits(app: &mut App) -> Duration {
+        let start = Instant::now();
+        app.update();
+        start.elapsed()
+    }
+
+    // the plain nested loop the broad phase replaced, every laser against every enemy
+    fn naive_laser_hit_enemy(
+        mut commands: Commands,
+        laser_query: Query<(Entity, &Transform, &Sprite), (With<Laser>, With<FromPlayer>)>,
+        mut enemy_query: Query<(Entity, &Transform, &TextureAtlasSprite, &Handle<TextureAtlas>, &mut Health, Option<&Boss>, Option<&EnemyKind>), With<Enemy>>,
+        texture_atlases: Res<Assets<TextureAtlas>>,
+        mut berserk_query: Query<(&Berserk, &mut Speed)>,
+        mut active_enemies: ResMut<ActiveEnemies>,
+        mut run_stats: ResMut<RunStats>,
+        mut enemy_killed: EventWriter<EnemyKilled>,
+        mut streak: ResMut<StreakState>
+    ) {
+        let mut enemies_blasted: HashSet<Entity> = HashSet::new();
+        for (laser_entity, laser_tf, laser_sprite) in laser_query.iter() {
+            let mut hit = false;
+            for (enemy_entity, enemy_tf, enemy_sprite, enemy_atlas, mut health, boss, kind) in enemy_query.iter_mut() {
+                if !aabb_collides(laser_tf, laser_sprite.size, enemy_tf, frame_size(&texture_atlases, enemy_atlas, enemy_sprite)) {
+                    continue;
+                }
+                hit = true;
+                if !enemies_blasted.contains(&enemy_entity)
+                    && damage_enemy(&mut commands, &mut active_enemies, &mut enemy_killed, &mut berserk_query, enemy_entity, enemy_tf, &mut health, boss, kind, 1) {
+                    enemies_blasted.insert(enemy_entity);
+                    streak.kills_since_death += 1;
+                }
+                commands.entity(laser_entity).despawn();
+            }
+            if hit {
+                run_stats.shots_hit += 1;
+            }
+        }
+    }
+
+    // what a frame of hits left behind: where the survivors are and their health,
+    // how many lasers are still flying, and the tallies
+    fn outcome(app: &mut App) -> (Vec<(u32, u32, u32)>, usize, u32, u32, u32) {
+        let world = &mut app.world;
+        let mut enemies: Vec<(u32, u32, u32)> = world
+            .query_filtered::<(&Transform, &Health), With<Enemy>>()
+            .iter(world)
+            .map(|(tf, health)| (tf.translation.x.to_bits(), tf.translation.y.to_bits(), health.current))
+            .collect();
+        enemies.sort_unstable();
+        let lasers = world.query_filtered::<Entity, With<Laser>>().iter(world).count();
+        let shots_hit = world.get_resource::<RunStats>().unwrap().shots_hit;
+        let kills = world.get_resource::<StreakState>().unwrap().kills_since_death;
+        let active = world.get_resource::<ActiveEnemies>().unwrap().0;
+        (enemies, lasers, shots_hit, kills, active)
+    }
+
+    #[test]
+    fn overlapping_boxes_collide() {
+        let a = Transform::from_xyz(0., 0., 0.);
+        let b = Transform::from_xyz(10., 5., 0.);
+        assert!(aabb_collides(&a, Vec2::new(16., 16.), &b, Vec2::new(16., 16.)));
+    }
+
+    #[test]
+    fn separate_boxes_dont_collide() {
+        let a = Transform::from_xyz(0., 0., 0.);
+        let b = Transform::from_xyz(20., 0., 0.);
+        assert!(!aabb_collides(&a, Vec2::new(16., 16.), &b, Vec2::new(16., 16.)));
+    }
+
+    #[test]
+    fn flipped_sprites_collide_on_their_scaled_size() {
+        // 16px boxes 20px apart only touch once both are scaled up, one of them flipped
+        let a = Transform { translation: Vec3::ZERO, scale: Vec3::new(2., -2., 1.), ..Default::default() };
+        let b = Transform { translation: Vec3::new(20., 0., 0.), scale: Vec3::new(2., 2., 1.), ..Default::default() };
+        assert!(aabb_collides(&a, Vec2::new(16., 16.), &b, Vec2::new(16., 16.)));
+    }
+
+    #[test]
+    fn lasers_past_the_top_margin_are_despawned() {
+        let mut world = World::default();
+        world.insert_resource(WindowSize { width: 598., height: 676. });
+        let top = 676. / 2. + OFFSCREEN_MARGIN;
+        let on_screen = world.spawn().insert_bundle((Laser, FromPlayer, Transform::from_xyz(0., 300., 0.))).id();
+        let in_margin = world.spawn().insert_bundle((Laser, FromPlayer, Transform::from_xyz(0., top - 1., 0.))).id();
+        let gone = world.spawn().insert_bundle((Laser, FromPlayer, Transform::from_xyz(0., top + 1., 0.))).id();
+
+        SystemStage::single(despawn_out_of_bounds.system()).run(&mut world);
+
+        assert!(world.get_entity(on_screen).is_some());
+        assert!(world.get_entity(in_margin).is_some());
+        assert!(world.get_entity(gone).is_none());
+    }
+
+    #[test]
+    fn a_long_frame_finishes_the_explosion() {
+        let mut app = headless_app();
+        let sheet = sprite_sheet(&mut app, Vec2::new(16., 16.), 4);
+        let explosion = app.world_mut()
+            .spawn()
+            .insert_bundle((Explosion, Timer::from_seconds(1., true), TextureAtlasSprite::default(), sheet))
+            .id();
+        // run the system by hand, the app's own clock would tick on real time
+        let mut world = app.app.world;
+        world.insert_resource(Time::default());
+        let mut stage = SystemStage::single(animate_explosion.system());
+
+        // Bevy keeps Time::update_with_instant to itself, so take whatever delta
+        // a bare update gives and shrink the sheet frames to a tenth of it:
+        // one frame then covers ten sheet frames on a four frame sheet
+        let mut time = world.get_resource_mut::<Time>().unwrap();
+        let delta = loop {
+            time.update();
+            if time.delta() > Duration::ZERO {
+                break time.delta();
+            }
+        };
+        world.get_mut::<Timer>(explosion).unwrap().set_duration(delta / 10);
+        stage.run(&mut world);
+        assert!(world.get_entity(explosion).is_none());
+        // and nothing left over trips up the frames after
+        stage.run(&mut world);
+    }
+
+    #[test]
+    fn broad_phase_matches_the_naive_loop() {
+        let mut broad = collision_app(player_laser_hit_enemy.system());
+        let mut naive = collision_app(naive_laser_hit_enemy.system());
+        broad.update();
+        naive.update();
+        let result = outcome(&mut broad);
+        assert!(result.2 > 0);
+        assert_eq!(result, outcome(&mut naive));
+    }
+
+    // wall-clock timing, so it stays out of the normal run:
+    // cargo test --release -- --ignored laser_hits_fit_in_a_frame
+    #[test]
+    #[ignore]
+    fn laser_hits_fit_in_a_frame() {
+        // best of a few fresh scenes each, so one slow frame doesn't decide it
+        let fastest = |hit_system: fn() -> SystemDescriptor| (0..5).map(|_| time_hits(&mut collision_app(hit_system()))).min().unwrap();
+        let broad_time = fastest(|| player_laser_hit_enemy.system().into());
+        let naive_time = fastest(|| naive_laser_hit_enemy.system().into());
+        assert!(broad_time < Duration::from_secs_f64(1. / 60.), "{:?} for one frame", broad_time);
+        assert!(broad_time < naive_time, "broad phase {:?}, naive loop {:?}", broad_time, naive_time);
+    }
+}
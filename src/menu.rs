@@ -0,0 +1,293 @@
+use bevy::prelude::*;
+
+use crate::{AppState, ColorblindMode, DailyBest, Difficulty, FONT, GameMode, date_label, today, player::{AutoFire, CoopMode, Lives}};
+
+struct MenuText;
+struct DifficultyText;
+struct AutoFireText;
+struct GameModeText;
+struct ColorblindText;
+struct CoopText;
+pub struct MenuPlugin;
+
+impl Plugin for MenuPlugin {
+    fn build(&self, app: &mut AppBuilder) {
+        app
+            .add_system_set(
+                SystemSet::on_enter(AppState::MainMenu)
+                .with_system(menu_setup.system())
+            )
+            .add_system_set(
+                SystemSet::on_update(AppState::MainMenu)
+                .with_system(menu_start.system())
+                .with_system(select_difficulty.system())
+                .with_system(select_game_mode.system())
+                .with_system(toggle_colorblind.system())
+                .with_system(toggle_coop.system())
+                .with_system(update_auto_fire_text.system())
+            )
+            .add_system_set(
+                SystemSet::on_exit(AppState::MainMenu)
+                .with_system(menu_cleanup.system())
+            );
+    }
+}
+
+fn menu_setup(
+    mut commands: Commands,
+    asset_server: Res<AssetServer>,
+    difficulty: Res<Difficulty>,
+    game_mode: Res<GameMode>,
+    daily_best: Res<DailyBest>,
+    auto_fire: Res<AutoFire>,
+    colorblind: Res<ColorblindMode>,
+    coop: Res<CoopMode>
+) {
+    commands.spawn_bundle(Text2dBundle {
+        text: Text::with_section(
+            "Press Enter to Start",
+            TextStyle {
+                font: asset_server.load(FONT),
+                font_size: 32.,
+                color: Color::WHITE
+            },
+            TextAlignment {
+                vertical: VerticalAlign::Center,
+                horizontal: HorizontalAlign::Center
+            }
+        ),
+        // above the demo enemies flying behind it
+        transform: Transform::from_xyz(0., 0., 50.),
+        ..Default::default()
+    })
+    .insert(MenuText);
+
+    commands.spawn_bundle(Text2dBundle {
+        text: Text::with_section(
+            difficulty_label(&difficulty),
+            TextStyle {
+                font: asset_server.load(FONT),
+                font_size: 20.,
+                color: Color::WHITE
+            },
+            TextAlignment {
+                vertical: VerticalAlign::Center,
+                horizontal: HorizontalAlign::Center
+            }
+        ),
+        transform: Transform::from_xyz(0., -40., 50.),
+        ..Default::default()
+    })
+    .insert(MenuText)
+    .insert(DifficultyText);
+
+    commands.spawn_bundle(Text2dBundle {
+        text: Text::with_section(
+            auto_fire_label(&auto_fire),
+            TextStyle {
+                font: asset_server.load(FONT),
+                font_size: 20.,
+                color: Color::WHITE
+            },
+            TextAlignment {
+                vertical: VerticalAlign::Center,
+                horizontal: HorizontalAlign::Center
+            }
+        ),
+        transform: Transform::from_xyz(0., -70., 50.),
+        ..Default::default()
+    })
+    .insert(MenuText)
+    .insert(AutoFireText);
+
+    commands.spawn_bundle(Text2dBundle {
+        text: Text::with_section(
+            game_mode_label(&game_mode, &daily_best),
+            TextStyle {
+                font: asset_server.load(FONT),
+                font_size: 20.,
+                color: Color::WHITE
+            },
+            TextAlignment {
+                vertical: VerticalAlign::Center,
+                horizontal: HorizontalAlign::Center
+            }
+        ),
+        transform: Transform::from_xyz(0., -100., 50.),
+        ..Default::default()
+    })
+    .insert(MenuText)
+    .insert(GameModeText);
+
+    commands.spawn_bundle(Text2dBundle {
+        text: Text::with_section(
+            colorblind_label(&colorblind),
+            TextStyle {
+                font: asset_server.load(FONT),
+                font_size: 20.,
+                color: Color::WHITE
+            },
+            TextAlignment {
+                vertical: VerticalAlign::Center,
+                horizontal: HorizontalAlign::Center
+            }
+        ),
+        transform: Transform::from_xyz(0., -130., 50.),
+        ..Default::default()
+    })
+    .insert(MenuText)
+    .insert(ColorblindText);
+
+    commands.spawn_bundle(Text2dBundle {
+        text: Text::with_section(
+            coop_label(&coop),
+            TextStyle {
+                font: asset_server.load(FONT),
+                font_size: 20.,
+                color: Color::WHITE
+            },
+            TextAlignment {
+                vertical: VerticalAlign::Center,
+                horizontal: HorizontalAlign::Center
+            }
+        ),
+        transform: Transform::from_xyz(0., -160., 50.),
+        ..Default::default()
+    })
+    .insert(MenuText)
+    .insert(CoopText);
+
+    commands.spawn_bundle(Text2dBundle {
+        text: Text::with_section(
+            "Key bindings  (K)",
+            TextStyle {
+                font: asset_server.load(FONT),
+                font_size: 20.,
+                color: Color::WHITE
+            },
+            TextAlignment {
+                vertical: VerticalAlign::Center,
+                horizontal: HorizontalAlign::Center
+            }
+        ),
+        transform: Transform::from_xyz(0., -190., 50.),
+        ..Default::default()
+    })
+    .insert(MenuText);
+}
+
+fn colorblind_label(colorblind: &ColorblindMode) -> String {
+    format!("Colorblind lasers: {}  (C)", if colorblind.0 { "On" } else { "Off" })
+}
+
+fn toggle_colorblind(
+    keyboard_input: Res<Input<KeyCode>>,
+    mut colorblind: ResMut<ColorblindMode>,
+    mut query: Query<&mut Text, With<ColorblindText>>
+) {
+    if !keyboard_input.just_pressed(KeyCode::C) {
+        return;
+    }
+    colorblind.0 = !colorblind.0;
+    for mut text in query.iter_mut() {
+        text.sections[0].value = colorblind_label(&colorblind);
+    }
+}
+
+// the second player flies with WASD, fires with Left Ctrl, dashes with Z and swaps weapons with X
+fn coop_label(coop: &CoopMode) -> String {
+    format!("Players: {}  (P)", if coop.0 { 2 } else { 1 })
+}
+
+fn toggle_coop(
+    keyboard_input: Res<Input<KeyCode>>,
+    mut coop: ResMut<CoopMode>,
+    mut query: Query<&mut Text, With<CoopText>>
+) {
+    if !keyboard_input.just_pressed(KeyCode::P) {
+        return;
+    }
+    coop.0 = !coop.0;
+    for mut text in query.iter_mut() {
+        text.sections[0].value = coop_label(&coop);
+    }
+}
+
+fn game_mode_label(game_mode: &GameMode, daily_best: &DailyBest) -> String {
+    if *game_mode == GameMode::DailyChallenge {
+        let day = today();
+        return format!("Mode: {} {} (best {})  (B)", game_mode.name(), date_label(day), daily_best.on(day));
+    }
+    format!("Mode: {}  (B)", game_mode.name())
+}
+
+fn select_game_mode(
+    keyboard_input: Res<Input<KeyCode>>,
+    mut game_mode: ResMut<GameMode>,
+    daily_best: Res<DailyBest>,
+    mut query: Query<&mut Text, With<GameModeText>>
+) {
+    if !keyboard_input.just_pressed(KeyCode::B) {
+        return;
+    }
+    *game_mode = game_mode.next();
+    for mut text in query.iter_mut() {
+        text.sections[0].value = game_mode_label(&game_mode, &daily_best);
+    }
+}
+
+fn auto_fire_label(auto_fire: &AutoFire) -> String {
+    format!("Auto-fire: {}  (T)", if auto_fire.0 { "On" } else { "Off" })
+}
+
+// T itself is handled by the player plugin, this just reflects it
+fn update_auto_fire_text(auto_fire: Res<AutoFire>, mut query: Query<&mut Text, With<AutoFireText>>) {
+    if auto_fire.is_changed() {
+        for mut text in query.iter_mut() {
+            text.sections[0].value = auto_fire_label(&auto_fire);
+        }
+    }
+}
+
+fn difficulty_label(difficulty: &Difficulty) -> String {
+    format!("Difficulty: {}  (1/2/3)", difficulty.name())
+}
+
+fn select_difficulty(
+    keyboard_input: Res<Input<KeyCode>>,
+    mut difficulty: ResMut<Difficulty>,
+    mut query: Query<&mut Text, With<DifficultyText>>
+) {
+    let selected = if keyboard_input.just_pressed(KeyCode::Key1) {
+        Difficulty::Easy
+    } else if keyboard_input.just_pressed(KeyCode::Key2) {
+        Difficulty::Normal
+    } else if keyboard_input.just_pressed(KeyCode::Key3) {
+        Difficulty::Hard
+    } else {
+        return;
+    };
+    *difficulty = selected;
+    for mut text in query.iter_mut() {
+        text.sections[0].value = difficulty_label(&difficulty);
+    }
+}
+
+fn menu_start(
+    keyboard_input: Res<Input<KeyCode>>,
+    difficulty: Res<Difficulty>,
+    coop: Res<CoopMode>,
+    mut lives: ResMut<Lives>,
+    mut app_state: ResMut<State<AppState>>
+) {
+    // K on the same frame may already have queued the settings screen
+    if keyboard_input.just_pressed(KeyCode::Return) && app_state.set(AppState::InGame).is_ok() {
+        *lives = Lives::new(difficulty.lives(), coop.0);
+    }
+}
+
+fn menu_cleanup(mut commands: Commands, query: Query<Entity, With<MenuText>>) {
+    for entity in query.iter() {
+        commands.entity(entity).despawn();
+    }
+}
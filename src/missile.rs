@@ -0,0 +1,146 @@
+use std::f32::consts::PI;
+
+use bevy::prelude::*;
+
+use crate::{ActiveEnemies, AppState, DespawnOffscreen, EnemyKilled, ExplosionToSpawn, GameMode, Materials, Paused, Speed, aabb_collides, boss::Boss, damage_enemy, frame_size, enemy::{Berserk, Enemy, EnemyKind, Health, Shielded}, player::{Player, PlayerId}};
+
+const MISSILE_SPEED: f32 = 450.;
+// how fast a missile can swing toward its target, in radians per second
+const MISSILE_TURN_RATE: f32 = 4.;
+const MISSILE_DAMAGE: u32 = 3;
+const MISSILE_COOLDOWN: f32 = 3.;
+const MISSILE_WIDTH: f32 = 6.;
+const MISSILE_HEIGHT: f32 = 16.;
+
+pub struct HomingMissile {
+    velocity: Vec2
+}
+// the enemy a missile is chasing, if any is left
+struct Target(Option<Entity>);
+struct MissileCooldown(Timer);
+
+pub struct MissilePlugin;
+
+impl Plugin for MissilePlugin {
+    fn build(&self, app: &mut AppBuilder) {
+        app
+            .insert_resource(MissileCooldown(Timer::from_seconds(MISSILE_COOLDOWN, false)))
+            .add_system_set(
+                SystemSet::on_update(AppState::InGame)
+                .with_system(fire_missile.system())
+                .with_system(homing_missile_movement.system())
+                .with_system(missile_hit_enemy.system())
+            );
+    }
+}
+
+fn fire_missile(
+    mut commands: Commands,
+    materials: Res<Materials>,
+    keyboard_input: Res<Input<KeyCode>>,
+    time: Res<Time>,
+    paused: Res<Paused>,
+    game_mode: Res<GameMode>,
+    mut cooldown: ResMut<MissileCooldown>,
+    player_query: Query<(&Transform, &PlayerId), With<Player>>
+) {
+    if paused.0 || !game_mode.can_fire() {
+        return;
+    }
+    cooldown.0.tick(time.delta());
+    if !cooldown.0.finished() || !keyboard_input.just_pressed(KeyCode::Q) {
+        return;
+    }
+    // Q is on the first player's side
+    if let Some((player_tf, _)) = player_query.iter().find(|(_, id)| id.0 == 0) {
+        spawn_missile(&mut commands, &materials, player_tf.translation + Vec3::new(0., 20., 0.));
+        cooldown.0.reset();
+    }
+}
+
+// launched straight up, it finds its own target
+pub fn spawn_missile(commands: &mut Commands, materials: &Materials, translation: Vec3) {
+    commands
+        .spawn_bundle(SpriteBundle {
+            material: materials.missile.clone(),
+            sprite: Sprite::new(Vec2::new(MISSILE_WIDTH, MISSILE_HEIGHT)),
+            transform: Transform::from_xyz(translation.x, translation.y, 0.),
+            ..Default::default()
+        })
+        .insert(HomingMissile { velocity: Vec2::new(0., MISSILE_SPEED) })
+        .insert(Target(None))
+        .insert(DespawnOffscreen);
+}
+
+fn homing_missile_movement(
+    time: Res<Time>,
+    paused: Res<Paused>,
+    enemy_query: Query<(Entity, &Transform), (With<Enemy>, Without<HomingMissile>)>,
+    mut missile_query: Query<(&mut Transform, &mut HomingMissile, &mut Target)>
+) {
+    if paused.0 {
+        return;
+    }
+    let delta = time.delta_seconds();
+    for (mut tf, mut missile, mut target) in missile_query.iter_mut() {
+        let position = Vec2::new(tf.translation.x, tf.translation.y);
+        // re-acquire the nearest enemy whenever the current one is gone
+        let target_tf = match target.0.and_then(|enemy| enemy_query.get(enemy).ok()) {
+            Some((_, enemy_tf)) => Some(enemy_tf),
+            None => {
+                let nearest = enemy_query.iter().min_by(|(_, a), (_, b)| {
+                    let da = Vec2::new(a.translation.x, a.translation.y).distance_squared(position);
+                    let db = Vec2::new(b.translation.x, b.translation.y).distance_squared(position);
+                    da.partial_cmp(&db).unwrap()
+                });
+                target.0 = nearest.map(|(enemy, _)| enemy);
+                nearest.map(|(_, enemy_tf)| enemy_tf)
+            }
+        };
+
+        // swing the velocity toward the target, no faster than the turn rate
+        if let Some(target_tf) = target_tf {
+            let to_target = Vec2::new(target_tf.translation.x, target_tf.translation.y) - position;
+            let current = missile.velocity.y.atan2(missile.velocity.x);
+            let mut turn = to_target.y.atan2(to_target.x) - current;
+            if turn > PI {
+                turn -= 2. * PI;
+            } else if turn < -PI {
+                turn += 2. * PI;
+            }
+            let max_turn = MISSILE_TURN_RATE * delta;
+            let angle = current + turn.max(-max_turn).min(max_turn);
+            missile.velocity = Vec2::new(angle.cos(), angle.sin()) * MISSILE_SPEED;
+        }
+
+        tf.translation += (missile.velocity * delta).extend(0.);
+        // the sprite points up
+        tf.rotation = Quat::from_rotation_z(missile.velocity.y.atan2(missile.velocity.x) - PI / 2.);
+    }
+}
+
+fn missile_hit_enemy(
+    mut commands: Commands,
+    mut active_enemies: ResMut<ActiveEnemies>,
+    mut enemy_killed: EventWriter<EnemyKilled>,
+    texture_atlases: Res<Assets<TextureAtlas>>,
+    missile_query: Query<(Entity, &Transform, &Sprite), With<HomingMissile>>,
+    mut enemy_query: Query<(Entity, &Transform, &TextureAtlasSprite, &Handle<TextureAtlas>, &mut Health, Option<&Boss>, Option<&EnemyKind>, Option<&Shielded>), With<Enemy>>,
+    mut berserk_query: Query<(&Berserk, &mut Speed)>
+) {
+    for (missile_entity, missile_tf, missile_sprite) in missile_query.iter() {
+        for (enemy_entity, enemy_tf, enemy_sprite, enemy_atlas, mut health, boss, kind, shielded) in enemy_query.iter_mut() {
+            // skip enemies an earlier missile already finished off this frame
+            if health.current == 0 || !aabb_collides(missile_tf, missile_sprite.size, enemy_tf, frame_size(&texture_atlases, enemy_atlas, enemy_sprite)) {
+                continue;
+            }
+            // a guardian's shield takes the blast
+            if shielded.is_none() {
+                damage_enemy(&mut commands, &mut active_enemies, &mut enemy_killed, &mut berserk_query, enemy_entity, enemy_tf, &mut health, boss, kind, MISSILE_DAMAGE);
+            }
+            commands.spawn().insert(ExplosionToSpawn::small(missile_tf.translation));
+            commands.entity(missile_entity).despawn();
+            break;
+        }
+    }
+}
@@ -0,0 +1,153 @@
+use std::io::Cursor;
+
+use bevy::prelude::*;
+use hound::{SampleFormat, WavReader};
+use rodio::{OutputStream, OutputStreamHandle, Sink, Source, buffer::SamplesBuffer};
+
+use crate::AppState;
+
+const MUSIC: &str = "music.wav";
+const MUSIC_VOLUME: f32 = 0.5;
+const MUSIC_VOLUME_STEP: f32 = 0.1;
+const WARNING_SOUND: &str = "low_health.wav";
+
+pub struct MusicSettings {
+    pub volume: f32,
+    pub muted: bool
+}
+
+impl Default for MusicSettings {
+    fn default() -> Self {
+        Self {
+            volume: MUSIC_VOLUME,
+            muted: false
+        }
+    }
+}
+
+struct MusicTrack(Handle<AudioSource>);
+struct WarningTrack(Handle<AudioSource>);
+// set while a ship is down to its last life, the warning beeps loop until it clears
+#[derive(Default)]
+pub struct WarningSound(pub bool);
+
+// bevy's Audio can't stop, loop or change volume, so the music gets its own
+// rodio sink. The output stream isn't Send, hence a non-send resource.
+struct MusicOutput {
+    stream: Option<(OutputStream, OutputStreamHandle)>,
+    sink: Option<Sink>,
+    playing: bool,
+    warning_sink: Option<Sink>
+}
+
+pub struct MusicPlugin;
+
+impl Plugin for MusicPlugin {
+    fn build(&self, app: &mut AppBuilder) {
+        app
+            .insert_resource(MusicSettings::default())
+            .insert_resource(WarningSound::default())
+            .insert_non_send_resource(MusicOutput {
+                stream: OutputStream::try_default().ok(),
+                sink: None,
+                playing: false,
+                warning_sink: None
+            })
+            .add_startup_system(load_music.system())
+            .add_system_set(
+                SystemSet::on_enter(AppState::InGame)
+                .with_system(start_music.system())
+            )
+            .add_system_set(
+                SystemSet::on_enter(AppState::GameOver)
+                .with_system(stop_music.system())
+            )
+            .add_system_set(
+                SystemSet::on_enter(AppState::Victory)
+                .with_system(stop_music.system())
+            )
+            .add_system(music_control.system());
+    }
+}
+
+fn load_music(mut commands: Commands, asset_server: Res<AssetServer>) {
+    commands.insert_resource(MusicTrack(asset_server.load(MUSIC)));
+    commands.insert_resource(WarningTrack(asset_server.load(WARNING_SOUND)));
+}
+
+fn start_music(mut output: NonSendMut<MusicOutput>) {
+    output.playing = true;
+}
+
+fn stop_music(mut output: NonSendMut<MusicOutput>) {
+    output.playing = false;
+    if let Some(sink) = output.sink.take() {
+        sink.stop();
+    }
+}
+
+fn music_control(
+    keyboard_input: Res<Input<KeyCode>>,
+    mut settings: ResMut<MusicSettings>,
+    track: Res<MusicTrack>,
+    warning_track: Res<WarningTrack>,
+    warning_sound: Res<WarningSound>,
+    audio_sources: Res<Assets<AudioSource>>,
+    mut output: NonSendMut<MusicOutput>
+) {
+    if keyboard_input.just_pressed(KeyCode::M) {
+        settings.muted = !settings.muted;
+    }
+    if keyboard_input.just_pressed(KeyCode::Equals) {
+        settings.volume = (settings.volume + MUSIC_VOLUME_STEP).min(1.);
+    }
+    if keyboard_input.just_pressed(KeyCode::Minus) {
+        settings.volume = (settings.volume - MUSIC_VOLUME_STEP).max(0.);
+    }
+
+    // start the loop once the track has finished loading
+    let output = &mut *output;
+    if output.playing && output.sink.is_none() {
+        output.sink = start_loop(&output.stream, audio_sources.get(&track.0));
+    }
+
+    // the warning gets a sink of its own so it can stop without the music
+    if !warning_sound.0 {
+        if let Some(sink) = output.warning_sink.take() {
+            sink.stop();
+        }
+    } else if output.warning_sink.is_none() {
+        output.warning_sink = start_loop(&output.stream, audio_sources.get(&warning_track.0));
+    }
+
+    let volume = if settings.muted { 0. } else { settings.volume };
+    for sink in output.sink.iter().chain(output.warning_sink.iter()) {
+        sink.set_volume(volume);
+    }
+}
+
+// None while there's no output device or the sound is still loading
+fn start_loop(stream: &Option<(OutputStream, OutputStreamHandle)>, source: Option<&AudioSource>) -> Option<Sink> {
+    let (_, stream_handle) = stream.as_ref()?;
+    let sink = Sink::try_new(stream_handle).ok()?;
+    sink.append(decode_wav(&source?.bytes)?.repeat_infinite());
+    Some(sink)
+}
+
+// rodio's own decoder drags in mp3 symbols that only live inside the bevy
+// dylib, so the wav is decoded with hound instead
+fn decode_wav(bytes: &[u8]) -> Option<SamplesBuffer<i16>> {
+    let mut reader = WavReader::new(Cursor::new(bytes)).ok()?;
+    let spec = reader.spec();
+    if spec.sample_format != SampleFormat::Int || spec.bits_per_sample > 16 {
+        return None;
+    }
+
+    let shift = 16 - spec.bits_per_sample;
+    let samples = reader
+        .samples::<i32>()
+        .map(|sample| sample.map(|value| (value << shift) as i16))
+        .collect::<Result<Vec<_>, _>>()
+        .ok()?;
+    Some(SamplesBuffer::new(spec.channels, spec.sample_rate, samples))
+}
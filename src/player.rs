@@ -1,39 +1,270 @@
-use bevy::{core::FixedTimestep, prelude::*};
+use std::{collections::HashSet, time::Duration};
 
-use crate::{Materials, SCALE, Speed, TIME_PER_FRAME, WindowSize};
+use bevy::{core::FixedTimestep, prelude::*, render::camera::OrthographicProjection};
+use serde::{Deserialize, Serialize};
+
+use crate::{AppState, CameraZoom, ColorblindMode, GameConfig, GameMode, MainCamera, Materials, PLAYER_SPRITE, PLAYER_TWO_SPRITE, Paused, RunStats, SCALE, Speed, THRUSTER_FRAME_HEIGHT, THRUSTER_FRAMES, WindowSize, in_game, save_json, spawn_muzzle_flash, enemy::{Enemy, FromEnemy}, gameover::Continuing, missile::spawn_missile, music::WarningSound, powerup::{PiercingShot, SpreadShot}, settings::{Binding, KeyBindings}};
 
 const PLAYER_SPRITE_HEIGHT: f32 = 75.;
 const PLAYER_SPRITE_WIDTH: f32 = 144.;
-const PLAYER_RESPAWN_DELAY: f64 = 2.;
+pub const PLAYER_LIVES: u32 = 3;
+pub const MAX_PLAYERS: usize = 2;
+const PLAYER_INVULNERABLE_TIME: f32 = 2.;
+const PLAYER_BLINK_INTERVAL: f32 = 0.1;
+const LOW_HEALTH_VIGNETTE_SPRITE: &str = "vignette.png";
+// radians per second of the red pulse on a ship down to its last life
+const LOW_HEALTH_PULSE_RATE: f32 = 5.;
+const GAMEPAD_DEADZONE: f32 = 0.2;
+const PLAYER_FIRE_COOLDOWN: f32 = 0.2;
+const MISSILE_FIRE_COOLDOWN: f32 = 0.6;
+const CHARGE_THRESHOLD: f32 = 0.6;
+const CHARGED_LASER_SCALE: f32 = 2.5;
+const SPREAD_ANGLE: f32 = 0.2;
+const TRAIL_FADE_TIME: f32 = 0.15;
+const TRAIL_SCALE: f32 = 0.6;
+const MAX_TRAIL_PARTICLES: usize = 200;
+const INPUT_LOG_FILE: &str = "input_log.json";
+const DASH_KEY: KeyCode = KeyCode::LShift;
+const SWAP_WEAPON_KEY: KeyCode = KeyCode::E;
+// the second ship in co-op gets the left side of the keyboard
+const PLAYER_TWO_CONTROLS: PlayerControls = PlayerControls {
+    left: Binding::new(KeyCode::A, None),
+    right: Binding::new(KeyCode::D, None),
+    up: Binding::new(KeyCode::W, None),
+    down: Binding::new(KeyCode::S, None),
+    fire: Binding::new(KeyCode::LControl, None),
+    dash: KeyCode::Z,
+    swap_weapon: KeyCode::X
+};
+// the same keys, which the first player's alternates give up in co-op
+const PLAYER_TWO_KEYS: [KeyCode; 7] = [KeyCode::A, KeyCode::D, KeyCode::W, KeyCode::S, KeyCode::LControl, KeyCode::Z, KeyCode::X];
+// how far either side of the middle the two co-op ships come in
+const COOP_SPAWN_OFFSET: f32 = 80.;
+const DASH_DISTANCE: f32 = 120.;
+const DASH_TIME: f32 = 0.12;
+const DASH_COOLDOWN: f32 = 1.5;
+const THRUSTER_FRAME_TIME: f32 = 0.06;
+// the flame shrinks and dims while the ship sits still
+const THRUSTER_IDLE_SCALE: f32 = 0.6;
+const THRUSTER_IDLE_ALPHA: f32 = 0.6;
+// the title screen ship fires this often while there's anything to shoot
+const AI_FIRE_INTERVAL: f32 = 0.35;
+// an enemy laser this close sideways and this far above is worth dodging
+const AI_DODGE_WIDTH: f32 = 40.;
+const AI_DODGE_RANGE: f32 = 220.;
+// lined up closely enough to stop and shoot
+const AI_AIM_SLACK: f32 = 8.;
 
 pub struct Player;
+// which ship this is, 0 for the first player
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PlayerId(pub u8);
+// a second ship on the same keyboard, toggled on the menu
+pub struct CoopMode(pub bool);
 pub struct Laser;
 pub struct FromPlayer;
+pub struct ChargedLaser;
+// fire continuously while the key is held instead of once per press
+pub struct AutoFire(pub bool);
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WeaponMode {
+    Single,
+    Twin
+}
+// what the fire key shoots, swapped with E
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PlayerWeapon {
+    Laser,
+    Missiles
+}
+
+impl PlayerWeapon {
+    fn cooldown(&self) -> f32 {
+        match self {
+            PlayerWeapon::Laser => PLAYER_FIRE_COOLDOWN,
+            PlayerWeapon::Missiles => MISSILE_FIRE_COOLDOWN
+        }
+    }
+}
+// a fading trail sprite left behind by a player laser
+struct Fade(Timer);
+// where spawn_laser_trail last saw a laser, the next trail sprite goes there
+struct PrevPosition(Vec3);
+// passes through enemies, remembering which ones it already hit
+#[derive(Default)]
+pub struct Piercing(pub HashSet<Entity>);
+// velocity (px/s) of a laser that doesn't travel straight up
+pub struct LaserVelocity(pub Vec2);
+// red-tinted copies of the two ships and the vignette, their colors pulsed by low_health_warning
+struct LowHealthMaterials {
+    ships: [Handle<ColorMaterial>; MAX_PLAYERS],
+    vignette: Handle<ColorMaterial>
+}
+struct LowHealthVignette;
+// flies the title screen ship, its input stands in for the keyboard
+struct AiPilot {
+    input: PlayerInput,
+    fire: Timer
+}
+// the exhaust flame under a ship, a child so it follows along
+struct Thruster(Timer);
 struct PlayerReadyFire(bool);
+struct FireCooldown(Timer);
+// seconds the fire key has been held
+struct Charge(f32);
 pub struct PlayerPlugin;
+// ships left for each player, the second slot stays empty outside co-op
+pub struct Lives(pub [u32; MAX_PLAYERS]);
+// the first connected gamepad drives the first player
+struct ActiveGamepad(Option<Gamepad>);
+// counts down in game time, so a pause doesn't use it up
+pub struct Invulnerable(Timer);
+
+impl Invulnerable {
+    pub fn active(&self) -> bool {
+        !self.0.finished()
+    }
+}
+// a quick sideways burst with the dash key, lasers pass through while it lasts
+pub struct Dash {
+    cooldown: Timer,
+    active: Timer,
+    dir: f32
+}
+
+impl Default for Dash {
+    fn default() -> Self {
+        // both start out finished, ready to dash and not dashing
+        let mut cooldown = Timer::from_seconds(DASH_COOLDOWN, false);
+        cooldown.tick(cooldown.duration());
+        let mut active = Timer::from_seconds(DASH_TIME, false);
+        active.tick(active.duration());
+        Self { cooldown, active, dir: 0. }
+    }
+}
+
+impl Dash {
+    pub fn dashing(&self) -> bool {
+        !self.active.finished()
+    }
+
+    // how far the cooldown has come back, 1 once another dash is ready
+    pub fn ready_percent(&self) -> f32 {
+        self.cooldown.percent()
+    }
+}
+// the movement and fire keys held down during one frame
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PlayerInput {
+    pub left: bool,
+    pub right: bool,
+    pub up: bool,
+    pub down: bool,
+    pub fire: bool
+}
+// every frame of the current game, keyed by seconds since startup
+#[derive(Default, Serialize)]
+pub struct InputLog(pub Vec<(f64, PlayerInput)>);
+// indexed by PlayerId
 pub struct PlayerStatte {
-    on: bool,
-    last_shot: f64
+    on: [bool; MAX_PLAYERS],
+    last_shot: [f64; MAX_PLAYERS]
 }
 
 impl Default for PlayerStatte {
     fn default() -> Self {
         Self {
-            on: false,
-            last_shot: 0.
+            on: [false; MAX_PLAYERS],
+            last_shot: [0.; MAX_PLAYERS]
         }   
     }
 }
 
+impl Default for Lives {
+    fn default() -> Self {
+        Self::new(PLAYER_LIVES, false)
+    }
+}
+
+impl Lives {
+    pub fn new(per_player: u32, coop: bool) -> Self {
+        Self([per_player, if coop { per_player } else { 0 }])
+    }
+
+    // the game is over once nobody has a ship left
+    pub fn all_lost(&self) -> bool {
+        self.0.iter().all(|&lives| lives == 0)
+    }
+}
+
+struct PlayerControls {
+    left: Binding,
+    right: Binding,
+    up: Binding,
+    down: Binding,
+    fire: Binding,
+    dash: KeyCode,
+    swap_weapon: KeyCode
+}
+
+impl PlayerControls {
+    // the first player's keys are the rebindable ones
+    fn for_player(id: PlayerId, bindings: &KeyBindings, coop: &CoopMode) -> Self {
+        let shared = if coop.0 { &PLAYER_TWO_KEYS[..] } else { &[] };
+        match id.0 {
+            0 => Self {
+                left: bindings.left.without(shared),
+                right: bindings.right.without(shared),
+                up: bindings.up.without(shared),
+                down: bindings.down.without(shared),
+                fire: bindings.fire.without(shared),
+                dash: DASH_KEY,
+                swap_weapon: SWAP_WEAPON_KEY
+            },
+            _ => PLAYER_TWO_CONTROLS
+        }
+    }
+
+    fn input(&self, keyboard_input: &Input<KeyCode>) -> PlayerInput {
+        PlayerInput {
+            left: self.left.pressed(keyboard_input),
+            right: self.right.pressed(keyboard_input),
+            up: self.up.pressed(keyboard_input),
+            down: self.down.pressed(keyboard_input),
+            fire: self.fire.pressed(keyboard_input)
+        }
+    }
+}
+
+impl Default for FireCooldown {
+    fn default() -> Self {
+        // start out finished so the first shot isn't delayed
+        let mut timer = Timer::from_seconds(PLAYER_FIRE_COOLDOWN, false);
+        timer.tick(timer.duration());
+        Self(timer)
+    }
+}
+
+impl InputLog {
+    pub fn save(&self) {
+        save_json(INPUT_LOG_FILE, self);
+    }
+}
+
 impl PlayerStatte {
-    pub fn shot(&mut self, time: f64) {
-        self.on = false;
-        self.last_shot = time;
+    pub fn shot(&mut self, id: PlayerId, time: f64) {
+        self.on[id.0 as usize] = false;
+        self.last_shot[id.0 as usize] = time;
     }
 
-    pub fn spawned(&mut self) {
-        self.on = true;
-        self.last_shot = 0.;
+    // false from the moment the ship is lost until it respawns
+    pub fn is_on(&self, id: PlayerId) -> bool {
+        self.on[id.0 as usize]
+    }
+
+    pub fn spawned(&mut self, id: PlayerId) {
+        self.on[id.0 as usize] = true;
+        self.last_shot[id.0 as usize] = 0.;
     }
 }
 
@@ -42,13 +273,59 @@ impl Plugin for PlayerPlugin {
     fn build(&self, app: &mut AppBuilder) {
        app
            .insert_resource(PlayerStatte::default())
-           .add_startup_stage("game_setup_actors", SystemStage::single(player_spawn.system()))
-           .add_system(player_movment.system())
-           .add_system(player_fire.system())
-           .add_system(laser_movment.system())
+           .insert_resource(Lives::default())
+           .insert_resource(CoopMode(false))
+           .insert_resource(ActiveGamepad(None))
+           .insert_resource(WeaponMode::Twin)
+           .insert_resource(AutoFire(false))
+           .insert_resource(InputLog::default())
+           .add_system(toggle_auto_fire.system())
+           .add_system(gamepad_connection.system())
+           .add_startup_system(setup_low_health_warning.system())
+           // in every state so leaving the game clears it
+           .add_system(low_health_warning.system())
+           // runs in every state so trails left at game over still fade out
+           .add_system(fade_particles.system())
+           .add_system_set(
+               SystemSet::on_enter(AppState::InGame)
+               .with_system(player_spawn.system())
+               .with_system(clear_input_log.system())
+           )
+           .add_system_set(
+               SystemSet::on_update(AppState::InGame)
+               .with_system(record_input.system())
+               .with_system(player_movment.system())
+               .with_system(player_fire.system())
+               .with_system(toggle_weapon_mode.system())
+               .with_system(swap_weapon.system())
+               .with_system(laser_movment.system())
+               .with_system(spawn_laser_trail.system())
+               .with_system(player_blink.system())
+               .with_system(animate_thruster.system())
+               .with_system(fire_cooldown.system())
+           )
+           // the title screen ship goes through the same movement and firing as a player
+           .add_system_set(
+               SystemSet::on_enter(AppState::MainMenu)
+               .with_system(spawn_ai_player.system())
+           )
+           .add_system_set(
+               SystemSet::on_update(AppState::MainMenu)
+               .with_system(ai_player_control.system())
+               .with_system(player_movment.system())
+               .with_system(player_fire.system())
+               .with_system(laser_movment.system())
+               .with_system(spawn_laser_trail.system())
+               .with_system(animate_thruster.system())
+               .with_system(fire_cooldown.system())
+           )
+           .add_system_set(
+               SystemSet::on_exit(AppState::MainMenu)
+               .with_system(despawn_ai_player.system())
+           )
            .add_system_set(
                SystemSet::new()
-               .with_run_criteria(FixedTimestep::step(0.5))
+               .with_run_criteria(FixedTimestep::step(0.5).chain(in_game.system()))
                .with_system(player_spawn.system())
             );
    
@@ -58,101 +335,749 @@ impl Plugin for PlayerPlugin {
 fn player_spawn(
     mut commands: Commands,
     materials: Res<Materials>,
+    config: Res<GameConfig>,
     window_size: Res<WindowSize>,
     time: Res<Time>,
-    mut player_state: ResMut<PlayerStatte>
+    lives: Res<Lives>,
+    coop: Res<CoopMode>,
+    mut player_state: ResMut<PlayerStatte>,
+    // the title screen ship may not be gone yet on the frame the game starts
+    player_query: Query<&PlayerId, (With<Player>, Without<AiPilot>)>
 ) {
     let now = time.seconds_since_startup();
-    let last_shot = player_state.last_shot;
     let window_bottom_point = -window_size.height / 2.;
     let padding = 5.;
-    
-    if !player_state.on && (last_shot == 0. || now > last_shot + PLAYER_RESPAWN_DELAY) {
-        commands.spawn_bundle(SpriteBundle {
-            material: materials.player.clone(),
-            transform: Transform {
-                translation: Vec3::new(0., window_bottom_point + PLAYER_SPRITE_HEIGHT / 4. + padding, 10.),
-                scale: Vec3::new(SCALE, SCALE, 1.0),
-                ..Default::default()
-            },
+    let players = if coop.0 { MAX_PLAYERS } else { 1 };
+
+    for index in 0..players {
+        let id = PlayerId(index as u8);
+        // player_spawn runs both on entering the game and on a timer, so never
+        // spawn over a ship that's already alive
+        let alive = player_query.iter().filter(|&&alive_id| alive_id == id).count();
+        debug_assert!(alive <= 1, "{} ships alive for {:?}", alive, id);
+        if alive > 0 {
+            continue;
+        }
+
+        let last_shot = player_state.last_shot[index];
+        if player_state.on[index] || lives.0[index] == 0 || (last_shot != 0. && now <= last_shot + config.respawn_delay) {
+            continue;
+        }
+        let (x, material) = match (coop.0, index) {
+            (false, _) => (0., materials.player.clone()),
+            (true, 0) => (-COOP_SPAWN_OFFSET, materials.player.clone()),
+            (true, _) => (COOP_SPAWN_OFFSET, materials.player_two.clone())
+        };
+        let ship = spawn_ship(&mut commands, &materials, &config, material, id, Vec3::new(x, window_bottom_point + PLAYER_SPRITE_HEIGHT / 4. + padding, 10.));
+        commands.entity(ship).insert(Invulnerable(Timer::from_seconds(PLAYER_INVULNERABLE_TIME, false)));
+        player_state.spawned(id);
+    }
+}
+
+// a ship with everything it needs to move and fire, and its thruster
+fn spawn_ship(
+    commands: &mut Commands,
+    materials: &Materials,
+    config: &GameConfig,
+    material: Handle<ColorMaterial>,
+    id: PlayerId,
+    translation: Vec3
+) -> Entity {
+    commands.spawn_bundle(SpriteBundle {
+        material,
+        transform: Transform {
+            translation,
+            scale: Vec3::new(SCALE, SCALE, 1.0),
+            ..Default::default()
+        },
+        ..Default::default()
+    })
+    .insert(Player)
+    .insert(id)
+    .insert(PlayerWeapon::Laser)
+    .insert(Speed(config.player_speed))
+    .insert(PlayerReadyFire(true))
+    .insert(FireCooldown::default())
+    .insert(Charge(0.))
+    .insert(Dash::default())
+    .with_children(|parent| {
+        parent.spawn_bundle(SpriteSheetBundle {
+            texture_atlas: materials.thruster.clone(),
+            // just below the ship, and behind it
+            transform: Transform::from_xyz(0., -(PLAYER_SPRITE_HEIGHT + THRUSTER_FRAME_HEIGHT) / 2., -1.),
             ..Default::default()
         })
-        .insert(Player)
-        .insert(Speed::default())
-        .insert(PlayerReadyFire(true))
-        .insert(WindowSize { width: window_size.width, height: window_size.height });
-        player_state.spawned();
+        .insert(Thruster(Timer::from_seconds(THRUSTER_FRAME_TIME, true)));
+    })
+    .id()
+}
+
+fn spawn_ai_player(
+    mut commands: Commands,
+    materials: Res<Materials>,
+    config: Res<GameConfig>,
+    window_size: Res<WindowSize>
+) {
+    let y = -window_size.height / 2. + PLAYER_SPRITE_HEIGHT / 4. + 5.;
+    let ship = spawn_ship(&mut commands, &materials, &config, materials.player.clone(), PlayerId(0), Vec3::new(0., y, 10.));
+    commands.entity(ship).insert(AiPilot {
+        input: PlayerInput::default(),
+        fire: Timer::from_seconds(AI_FIRE_INTERVAL, true)
+    });
+}
+
+fn despawn_ai_player(mut commands: Commands, query: Query<Entity, With<AiPilot>>) {
+    for entity in query.iter() {
+        commands.entity(entity).despawn_recursive();
     }
 }
 
+// dodges the nearest enemy laser coming down on it, otherwise lines up under
+// the nearest enemy and keeps shooting
+fn ai_player_control(
+    time: Res<Time>,
+    mut pilot_query: Query<(&Transform, &mut AiPilot)>,
+    enemy_query: Query<&Transform, With<Enemy>>,
+    laser_query: Query<&Transform, (With<Laser>, With<FromEnemy>)>
+) {
+    for (ship_tf, mut pilot) in pilot_query.iter_mut() {
+        let ship = ship_tf.translation;
+        let threat = nearest_x(ship.x, laser_query
+            .iter()
+            .map(|tf| tf.translation)
+            .filter(|laser| laser.y > ship.y && laser.y - ship.y < AI_DODGE_RANGE && (laser.x - ship.x).abs() < AI_DODGE_WIDTH)
+            .map(|laser| laser.x));
+        let target = nearest_x(ship.x, enemy_query.iter().map(|tf| tf.translation.x));
+
+        let (left, right) = match (threat, target) {
+            // step out from under it, away from the side it's on
+            (Some(laser_x), _) => (laser_x >= ship.x, laser_x < ship.x),
+            (None, Some(enemy_x)) if (enemy_x - ship.x).abs() > AI_AIM_SLACK => (enemy_x < ship.x, enemy_x > ship.x),
+            _ => (false, false)
+        };
+        let fire_due = pilot.fire.tick(time.delta()).just_finished();
+        pilot.input = PlayerInput {
+            left,
+            right,
+            fire: fire_due && target.is_some(),
+            ..Default::default()
+        };
+    }
+}
+
+fn nearest_x(x: f32, xs: impl Iterator<Item = f32>) -> Option<f32> {
+    xs.min_by(|a, b| (a - x).abs().partial_cmp(&(b - x).abs()).unwrap())
+}
+
+fn setup_low_health_warning(
+    mut commands: Commands,
+    asset_server: Res<AssetServer>,
+    mut materials: ResMut<Assets<ColorMaterial>>
+) {
+    let vignette = materials.add(asset_server.load(LOW_HEALTH_VIGNETTE_SPRITE).into());
+    // over the world, under the screen flash
+    commands.spawn_bundle(SpriteBundle {
+        material: vignette.clone(),
+        transform: Transform::from_xyz(0., 0., 850.),
+        visible: Visible {
+            is_visible: false,
+            is_transparent: true
+        },
+        ..Default::default()
+    })
+    .insert(LowHealthVignette);
+    commands.insert_resource(LowHealthMaterials {
+        ships: [
+            materials.add(asset_server.load(PLAYER_SPRITE).into()),
+            materials.add(asset_server.load(PLAYER_TWO_SPRITE).into())
+        ],
+        vignette
+    });
+}
+
+// a ship on its last life pulses red, with a red edge around the screen and
+// the warning beeps until another life comes along
+fn low_health_warning(
+    time: Res<Time>,
+    app_state: Res<State<AppState>>,
+    lives: Res<Lives>,
+    coop: Res<CoopMode>,
+    window_size: Res<WindowSize>,
+    zoom: Res<CameraZoom>,
+    materials: Res<Materials>,
+    warning_materials: Res<LowHealthMaterials>,
+    mut color_materials: ResMut<Assets<ColorMaterial>>,
+    mut warning_sound: ResMut<WarningSound>,
+    mut player_query: Query<(&PlayerId, &mut Handle<ColorMaterial>), With<Player>>,
+    mut vignette_query: Query<(&mut Sprite, &mut Visible), With<LowHealthVignette>>
+) {
+    let in_game = *app_state.current() == AppState::InGame;
+    let last_life = |id: usize| in_game && lives.0[id] == 1;
+    // the second slot is empty outside co-op
+    let warning = in_game && lives.0.contains(&1);
+    if warning_sound.0 != warning {
+        warning_sound.0 = warning;
+    }
+
+    for (&id, mut material) in player_query.iter_mut() {
+        let index = id.0 as usize;
+        let wanted = if last_life(index) {
+            &warning_materials.ships[index]
+        } else if coop.0 && index > 0 {
+            &materials.player_two
+        } else {
+            &materials.player
+        };
+        if *material != *wanted {
+            *material = wanted.clone();
+        }
+    }
+
+    let pulse = 0.5 + 0.5 * (time.seconds_since_startup() as f32 * LOW_HEALTH_PULSE_RATE).sin();
+    if warning {
+        for handle in warning_materials.ships.iter() {
+            if let Some(material) = color_materials.get_mut(handle) {
+                material.color = Color::rgb(1., 1. - 0.7 * pulse, 1. - 0.7 * pulse);
+            }
+        }
+        if let Some(material) = color_materials.get_mut(&warning_materials.vignette) {
+            material.color = Color::rgba(1., 1., 1., 0.3 + 0.4 * pulse);
+        }
+    }
+    for (mut sprite, mut visible) in vignette_query.iter_mut() {
+        visible.is_visible = warning;
+        // framing whatever the camera shows
+        sprite.size = Vec2::new(window_size.width, window_size.height) * zoom.0;
+    }
+}
+
+fn player_blink(
+    mut commands: Commands,
+    time: Res<Time>,
+    paused: Res<Paused>,
+    mut query: Query<(Entity, &mut Invulnerable, &mut Visible), With<Player>>
+) {
+    if paused.0 {
+        return;
+    }
+    for (entity, mut invulnerable, mut visible) in query.iter_mut() {
+        if invulnerable.0.tick(time.delta()).finished() {
+            visible.is_visible = true;
+            commands.entity(entity).remove::<Invulnerable>();
+        } else {
+            visible.is_visible = (invulnerable.0.elapsed_secs() / PLAYER_BLINK_INTERVAL) as u32 % 2 == 0;
+        }
+    }
+}
+
+fn gamepad_connection(mut active_gamepad: ResMut<ActiveGamepad>, mut gamepad_events: EventReader<GamepadEvent>) {
+    for GamepadEvent(gamepad, event_type) in gamepad_events.iter() {
+        match event_type {
+            GamepadEventType::Connected if active_gamepad.0.is_none() => {
+                active_gamepad.0 = Some(*gamepad);
+            }
+            GamepadEventType::Disconnected if active_gamepad.0 == Some(*gamepad) => {
+                active_gamepad.0 = None;
+            }
+            _ => {}
+        }
+    }
+}
+
+// a continued run keeps logging onto the inputs that led up to it
+fn clear_input_log(mut input_log: ResMut<InputLog>, continuing: Res<Continuing>) {
+    if continuing.0 {
+        return;
+    }
+    input_log.0.clear();
+}
+
+fn record_input(
+    keyboard_input: Res<Input<KeyCode>>,
+    bindings: Res<KeyBindings>,
+    coop: Res<CoopMode>,
+    time: Res<Time>,
+    paused: Res<Paused>,
+    mut input_log: ResMut<InputLog>
+) {
+    if paused.0 {
+        return;
+    }
+    let input = PlayerControls::for_player(PlayerId(0), &bindings, &coop).input(&keyboard_input);
+    input_log.0.push((time.seconds_since_startup(), input));
+}
 
 fn player_movment(
     keyboard_input: Res<Input<KeyCode>>,
-    mut query: Query<(&Speed, &mut Transform, With<Player>, &WindowSize)>
+    bindings: Res<KeyBindings>,
+    coop: Res<CoopMode>,
+    gamepad_axes: Res<Axis<GamepadAxis>>,
+    active_gamepad: Res<ActiveGamepad>,
+    time: Res<Time>,
+    paused: Res<Paused>,
+    window_size: Res<WindowSize>,
+    mut query: Query<(&PlayerId, &Speed, &mut Transform, &mut Dash, &Children, Option<&AiPilot>), With<Player>>,
+    mut thruster_query: Query<(&mut Transform, &mut TextureAtlasSprite), (With<Thruster>, Without<Player>)>
 ) {
-    if let Ok((speed, mut transform, _, window_size)) = query.single_mut() {
-        let dir = if keyboard_input.pressed(KeyCode::Left) {
+    if paused.0 {
+        return;
+    }
+    for (&id, speed, mut transform, mut dash, children, pilot) in query.iter_mut() {
+        let controls = PlayerControls::for_player(id, &bindings, &coop);
+        // the gamepad belongs to the first player
+        let stick = |axis_type: GamepadAxisType| active_gamepad.0
+            .filter(|_| id.0 == 0 && pilot.is_none())
+            .and_then(|gamepad| gamepad_axes.get(GamepadAxis(gamepad, axis_type)))
+            .filter(|value| value.abs() > GAMEPAD_DEADZONE)
+            .unwrap_or(0.);
+        let (stick_x, stick_y) = (stick(GamepadAxisType::LeftStickX), stick(GamepadAxisType::LeftStickY));
+        // the title screen ship goes where its pilot says, not the keys
+        let input = pilot.map_or_else(|| controls.input(&keyboard_input), |pilot| pilot.input);
+        let dir = if input.left {
             -1.
-        } else if keyboard_input.pressed(KeyCode::Right) {
+        } else if input.right {
+            1.
+        } else {
+            stick_x
+        };
+        let y_dir = if input.up {
             1.
+        } else if input.down {
+            -1.
         } else {
-            0.
+            stick_y
         };
         
-        let movement = dir * speed.0 * TIME_PER_FRAME;
+        dash.cooldown.tick(time.delta());
+        dash.active.tick(time.delta());
+        // dashes the way the ship is heading, standing still there's no way to pick
+        if pilot.is_none() && keyboard_input.just_pressed(controls.dash) && dash.cooldown.finished() && dir != 0. {
+            dash.cooldown.reset();
+            dash.active.reset();
+            dash.dir = dir.signum();
+        }
+
+        let mut movement = dir * speed.0 * time.delta_seconds();
+        if dash.dashing() {
+            movement += dash.dir * DASH_DISTANCE / DASH_TIME * time.delta_seconds();
+        }
         let limit = (window_size.width / 2.) - (PLAYER_SPRITE_WIDTH / 4.);
-        let reach_limit = transform.translation.x + movement > limit || transform.translation.x + movement < -limit;
-        if reach_limit == false {
-            transform.translation.x += movement;
+        // clamp rather than block so a shrinking window pulls the ship back in
+        transform.translation.x = (transform.translation.x + movement).max(-limit).min(limit);
+
+        // the ship stays within the bottom third of the window
+        let y_movement = y_dir * speed.0 * time.delta_seconds();
+        let window_bottom_point = -window_size.height / 2.;
+        let y_min = window_bottom_point + PLAYER_SPRITE_HEIGHT / 4.;
+        let y_max = window_bottom_point + window_size.height / 3. - PLAYER_SPRITE_HEIGHT / 4.;
+        transform.translation.y = (transform.translation.y + y_movement).min(y_max).max(y_min);
+
+        // full burn while the ship is going anywhere
+        let (scale, alpha) = if dir != 0. || y_dir != 0. || dash.dashing() {
+            (1., 1.)
+        } else {
+            (THRUSTER_IDLE_SCALE, THRUSTER_IDLE_ALPHA)
+        };
+        for &child in children.iter() {
+            if let Ok((mut thruster_tf, mut sprite)) = thruster_query.get_mut(child) {
+                thruster_tf.scale = Vec3::new(scale, scale, 1.);
+                // shorter flames stay tucked up against the ship
+                thruster_tf.translation.y = -(PLAYER_SPRITE_HEIGHT + THRUSTER_FRAME_HEIGHT * scale) / 2.;
+                sprite.color.set_a(alpha);
+            }
         }
     }
 }
 
+fn animate_thruster(
+    time: Res<Time>,
+    paused: Res<Paused>,
+    player_query: Query<&Visible, (With<Player>, Without<Thruster>)>,
+    mut query: Query<(&Parent, &mut Thruster, &mut TextureAtlasSprite, &mut Visible)>
+) {
+    if paused.0 {
+        return;
+    }
+    for (parent, mut thruster, mut sprite, mut visible) in query.iter_mut() {
+        if thruster.0.tick(time.delta()).just_finished() {
+            sprite.index = (sprite.index + 1) % THRUSTER_FRAMES as u32;
+        }
+        // blinks along with the ship while it's invulnerable
+        if let Ok(player_visible) = player_query.get(parent.0) {
+            visible.is_visible = player_visible.is_visible;
+        }
+    }
+}
+
+fn fire_cooldown(time: Res<Time>, mut query: Query<&mut FireCooldown, With<Player>>) {
+    for mut cooldown in query.iter_mut() {
+        cooldown.0.tick(time.delta());
+    }
+}
+
 fn player_fire(
     mut commands: Commands,
     materials: Res<Materials>,
+    config: Res<GameConfig>,
     keyboard_input: Res<Input<KeyCode>>,
-    mut query: Query<(&Transform, &mut PlayerReadyFire, With<Player>)>
+    bindings: Res<KeyBindings>,
+    gamepad_buttons: Res<Input<GamepadButton>>,
+    active_gamepad: Res<ActiveGamepad>,
+    mouse_buttons: Res<Input<MouseButton>>,
+    windows: Res<Windows>,
+    camera_query: Query<(&Transform, &OrthographicProjection), With<MainCamera>>,
+    weapon_mode: Res<WeaponMode>,
+    auto_fire: Res<AutoFire>,
+    colorblind: Res<ColorblindMode>,
+    // grouped to stay within bevy's 16 system params
+    (paused, game_mode, coop, mut run_stats): (Res<Paused>, Res<GameMode>, Res<CoopMode>, ResMut<RunStats>),
+    time: Res<Time>,
+    mut query: Query<(&PlayerId, &Transform, &PlayerWeapon, &mut PlayerReadyFire, &mut FireCooldown, &mut Charge, Option<&SpreadShot>, Option<&PiercingShot>, Option<&AiPilot>), With<Player>>
 ) {
-    if let Ok((transform, mut ready_fire, _)) = query.single_mut() {
-        if ready_fire.0 && keyboard_input.pressed(KeyCode::Space) {
-            let (x, y): (f32, f32) = (transform.translation.x, transform.translation.y);
-            let mut spawn_lasers = |x_offset: f32| { 
-                commands.spawn_bundle(SpriteBundle {
-                    material: materials.player_laser.clone(),
+    if paused.0 {
+        return;
+    }
+    for (&id, transform, weapon, mut ready_fire, mut cooldown, mut charge, spread_shot, piercing_shot, pilot) in query.iter_mut() {
+        // the mode picked on the menu doesn't hold back the title screen ship
+        if pilot.is_none() && !game_mode.can_fire() {
+            continue;
+        }
+        // the gamepad and mouse belong to the first player
+        let first_player = id.0 == 0 && pilot.is_none();
+        let fire_pressed = match pilot {
+            Some(pilot) => pilot.input.fire,
+            None => PlayerControls::for_player(id, &bindings, &coop).fire.pressed(&keyboard_input) || active_gamepad.0
+                .filter(|_| first_player)
+                .map_or(false, |gamepad| gamepad_buttons.pressed(GamepadButton(gamepad, GamepadButtonType::South)))
+        };
+        let (x, y): (f32, f32) = (transform.translation.x, transform.translation.y);
+        // left click fires a single shot toward the cursor
+        let mouse_aim = if first_player && mouse_buttons.pressed(MouseButton::Left) {
+            cursor_world_position(&windows, &camera_query).map(|cursor| {
+                let dir = cursor - Vec2::new(x, y + 15.);
+                (-dir.x).atan2(dir.y)
+            })
+        } else {
+            None
+        };
+        let trigger_pressed = fire_pressed || mouse_aim.is_some();
+        // holding the fire key charges a bigger shot, so a laser shot waits for
+        // the release and goes out as either the charged laser or the normal
+        // volley, unless holding it is auto firing or there are only missiles
+        let chargeable = !auto_fire.0 && *weapon == PlayerWeapon::Laser && mouse_aim.is_none();
+        let released = chargeable && !fire_pressed && !ready_fire.0;
+        let charged = released && charge.0 >= CHARGE_THRESHOLD;
+        let fire = if chargeable { released && !charged } else { (ready_fire.0 || auto_fire.0) && trigger_pressed };
+
+        if fire && cooldown.0.finished() {
+            // angle is counter-clockwise from straight up
+            let mut spawn_lasers = |x_offset: f32, angle: f32| { 
+                let speed = Speed(config.laser_speed);
+                let mut laser = commands.spawn_bundle(SpriteBundle {
+                    material: materials.player_laser(&colorblind),
                     transform: Transform { 
                         translation: Vec3::new(x + x_offset, y + 15., 0.),
+                        rotation: Quat::from_rotation_z(angle),
                         ..Default::default()
                     },
                     ..Default::default() 
-                })
-                .insert(Laser)
-                .insert(FromPlayer)
-                .insert(Speed::default());
+                });
+                if angle != 0. {
+                    laser.insert(LaserVelocity(Vec2::new(-angle.sin(), angle.cos()) * speed.0));
+                }
+                if piercing_shot.is_some() {
+                    laser.insert(Piercing::default());
+                }
+                laser
+                    .insert(Laser)
+                    .insert(FromPlayer)
+                    .insert(speed);
+                run_stats.shots_fired += 1;
+                spawn_muzzle_flash(&mut commands, &materials, Vec3::new(x + x_offset, y + 15., 0.));
             };
 
-            let x_offset = PLAYER_SPRITE_WIDTH / 4. - 5.;
-            spawn_lasers(x_offset);
-            spawn_lasers(-x_offset);
+            if *weapon == PlayerWeapon::Missiles {
+                spawn_missile(&mut commands, &materials, Vec3::new(x, y + 20., 0.));
+            } else if let Some(angle) = mouse_aim {
+                spawn_lasers(0., angle);
+            } else if spread_shot.is_some() {
+                spawn_lasers(0., SPREAD_ANGLE);
+                spawn_lasers(0., 0.);
+                spawn_lasers(0., -SPREAD_ANGLE);
+            } else {
+                match *weapon_mode {
+                    WeaponMode::Single => spawn_lasers(0., 0.),
+                    WeaponMode::Twin => {
+                        let x_offset = PLAYER_SPRITE_WIDTH / 4. - 5.;
+                        spawn_lasers(x_offset, 0.);
+                        spawn_lasers(-x_offset, 0.);
+                    }
+                }
+            }
             
             ready_fire.0 = false;
+            cooldown.0.set_duration(Duration::from_secs_f32(weapon.cooldown()));
+            cooldown.0.reset();
+        }
+
+        if chargeable && fire_pressed {
+            // held, the shot goes out once the key comes up
+            ready_fire.0 = false;
+            charge.0 += time.delta_seconds();
+        } else {
+            if charged {
+                commands.spawn_bundle(SpriteBundle {
+                    material: materials.player_laser(&colorblind),
+                    transform: Transform {
+                        translation: Vec3::new(x, y + 15., 0.),
+                        scale: Vec3::new(CHARGED_LASER_SCALE, CHARGED_LASER_SCALE, 1.),
+                        ..Default::default()
+                    },
+                    ..Default::default()
+                })
+                .insert(Laser)
+                .insert(FromPlayer)
+                .insert(ChargedLaser)
+                .insert(Speed(config.charged_laser_speed));
+                run_stats.shots_fired += 1;
+                spawn_muzzle_flash(&mut commands, &materials, Vec3::new(x, y + 15., 0.));
+            }
+            charge.0 = 0.;
         }
 
-        if keyboard_input.just_released(KeyCode::Space) {
+        // re-arm once neither the key nor the button is held
+        if !trigger_pressed {
             ready_fire.0 = true;
         }
     }
 }
 
+fn toggle_auto_fire(keyboard_input: Res<Input<KeyCode>>, mut auto_fire: ResMut<AutoFire>) {
+    if keyboard_input.just_pressed(KeyCode::T) {
+        auto_fire.0 = !auto_fire.0;
+    }
+}
+
+// only between shots, and the swap itself costs a full cooldown
+fn swap_weapon(
+    keyboard_input: Res<Input<KeyCode>>,
+    bindings: Res<KeyBindings>,
+    coop: Res<CoopMode>,
+    paused: Res<Paused>,
+    mut query: Query<(&PlayerId, &mut PlayerWeapon, &mut FireCooldown), With<Player>>
+) {
+    if paused.0 {
+        return;
+    }
+    for (&id, mut weapon, mut cooldown) in query.iter_mut() {
+        if !keyboard_input.just_pressed(PlayerControls::for_player(id, &bindings, &coop).swap_weapon) || !cooldown.0.finished() {
+            continue;
+        }
+        *weapon = match *weapon {
+            PlayerWeapon::Laser => PlayerWeapon::Missiles,
+            PlayerWeapon::Missiles => PlayerWeapon::Laser
+        };
+        cooldown.0.set_duration(Duration::from_secs_f32(weapon.cooldown()));
+        cooldown.0.reset();
+    }
+}
+
+fn toggle_weapon_mode(keyboard_input: Res<Input<KeyCode>>, paused: Res<Paused>, mut weapon_mode: ResMut<WeaponMode>) {
+    if !paused.0 && keyboard_input.just_pressed(KeyCode::Tab) {
+        *weapon_mode = match *weapon_mode {
+            WeaponMode::Single => WeaponMode::Twin,
+            WeaponMode::Twin => WeaponMode::Single
+        };
+    }
+}
+
+// the cursor mapped through the main camera into world space
+fn cursor_world_position(windows: &Windows, camera_query: &Query<(&Transform, &OrthographicProjection), With<MainCamera>>) -> Option<Vec2> {
+    let window = windows.get_primary()?;
+    let cursor = window.cursor_position()?;
+    let (camera_tf, projection) = camera_query.single().ok()?;
+    let window_size = Vec2::new(window.width(), window.height());
+    // a zoomed out camera shows more world per pixel
+    let world = camera_tf.compute_matrix() * ((cursor - window_size / 2.) * projection.scale).extend(0.).extend(1.);
+    Some(Vec2::new(world.x, world.y))
+}
+
 fn laser_movment(
+    time: Res<Time>,
+    paused: Res<Paused>,
+    mut query: Query<(&Speed, Option<&LaserVelocity>, &mut Transform, (With<Laser>, With<FromPlayer>))>
+) {
+    if paused.0 {
+        return;
+    }
+    for (speed, velocity, mut transform, _) in query.iter_mut() {
+        let velocity = velocity.map_or(Vec2::new(0., speed.0), |velocity| velocity.0);
+        transform.translation += (velocity * time.delta_seconds()).extend(0.);
+    }
+}
+
+fn spawn_laser_trail(
     mut commands: Commands,
-    window_size: Res<WindowSize>,
-    mut query: Query<(Entity, &Speed, &mut Transform, (With<Laser>, With<FromPlayer>))>
+    materials: Res<Materials>,
+    paused: Res<Paused>,
+    mut laser_query: Query<(Entity, &Transform, Option<&mut PrevPosition>), (With<Laser>, With<FromPlayer>)>,
+    particle_query: Query<(), With<Fade>>
 ) {
-    for (laser_entity, speed, mut transform, _) in query.iter_mut() {
-        transform.translation.y += speed.0 * TIME_PER_FRAME;
-        if transform.translation.y > window_size.height {
-            commands.entity(laser_entity).despawn();
+    if paused.0 {
+        return;
+    }
+    // cap the live particles so a screen full of lasers can't run away
+    let mut room = MAX_TRAIL_PARTICLES.saturating_sub(particle_query.iter().count());
+    for (entity, tf, prev_position) in laser_query.iter_mut() {
+        let previous = match prev_position {
+            Some(mut prev_position) => std::mem::replace(&mut prev_position.0, tf.translation),
+            // nothing to leave behind until it has moved once
+            None => {
+                commands.entity(entity).insert(PrevPosition(tf.translation));
+                continue;
+            }
+        };
+        if room == 0 {
+            continue;
         }
+        room -= 1;
+        commands.spawn_bundle(SpriteBundle {
+            material: materials.laser_trail[0].clone(),
+            transform: Transform {
+                translation: previous,
+                rotation: tf.rotation,
+                scale: tf.scale * TRAIL_SCALE
+            },
+            visible: Visible {
+                is_visible: true,
+                is_transparent: true
+            },
+            ..Default::default()
+        })
+        .insert(Fade(Timer::from_seconds(TRAIL_FADE_TIME, false)));
+    }
+}
+
+fn fade_particles(
+    mut commands: Commands,
+    materials: Res<Materials>,
+    time: Res<Time>,
+    paused: Res<Paused>,
+    mut query: Query<(Entity, &mut Fade, &mut Handle<ColorMaterial>)>
+) {
+    if paused.0 {
+        return;
+    }
+    let steps = materials.laser_trail.len();
+    for (entity, mut fade, mut material) in query.iter_mut() {
+        fade.0.tick(time.delta());
+        if fade.0.finished() {
+            commands.entity(entity).despawn();
+            continue;
+        }
+        // step through the pre-made materials rather than one per particle
+        let step = ((fade.0.percent() * steps as f32) as usize).min(steps - 1);
+        if *material != materials.laser_trail[step] {
+            *material = materials.laser_trail[step].clone();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn only_one_ship_is_ever_spawned() {
+        let mut world = World::default();
+        world.insert_resource(Materials::default());
+        world.insert_resource(GameConfig::default());
+        world.insert_resource(WindowSize { width: 598., height: 676. });
+        world.insert_resource(Time::default());
+        world.insert_resource(Lives::default());
+        world.insert_resource(CoopMode(false));
+        world.insert_resource(PlayerStatte::default());
+
+        // once on entering the game and once from the timer, on the same frame
+        let mut stage = SystemStage::single(player_spawn.system());
+        stage.run(&mut world);
+        stage.run(&mut world);
+        // even if the state gets out of step, the ship already there is kept
+        *world.get_resource_mut::<PlayerStatte>().unwrap() = PlayerStatte::default();
+        stage.run(&mut world);
+
+        let ships = world.query_filtered::<Entity, With<Player>>().iter(&world).count();
+        assert_eq!(ships, 1);
+    }
+
+    // a lone ship and everything player_fire reads, with the fire key just pressed
+    fn firing_world() -> (World, Entity) {
+        let mut world = World::default();
+        world.insert_resource(Materials { muzzle_flash: vec![Handle::default()], ..Default::default() });
+        world.insert_resource(GameConfig::default());
+        world.insert_resource(KeyBindings::default());
+        world.insert_resource(Input::<GamepadButton>::default());
+        world.insert_resource(ActiveGamepad(None));
+        world.insert_resource(Input::<MouseButton>::default());
+        world.insert_resource(Windows::default());
+        world.insert_resource(WeaponMode::Twin);
+        world.insert_resource(AutoFire(false));
+        world.insert_resource(ColorblindMode(false));
+        world.insert_resource(Paused(false));
+        world.insert_resource(GameMode::Normal);
+        world.insert_resource(CoopMode(false));
+        world.insert_resource(RunStats::default());
+        world.insert_resource(Time::default());
+        let mut keys = Input::<KeyCode>::default();
+        keys.press(KeyCode::Space);
+        world.insert_resource(keys);
+        let ship = world
+            .spawn()
+            .insert_bundle((Player, PlayerId(0), Transform::default(), PlayerWeapon::Laser, PlayerReadyFire(true), FireCooldown::default(), Charge(0.)))
+            .id();
+        (world, ship)
+    }
+
+    // presses fire, holds it for `held` seconds and lets go, then counts the charged and normal lasers out
+    fn release_after(held: f32) -> (usize, usize) {
+        let (mut world, ship) = firing_world();
+        let mut stage = SystemStage::single(player_fire.system());
+        stage.run(&mut world);
+        // nothing goes out while the key is still down
+        assert_eq!(world.query_filtered::<Entity, With<Laser>>().iter(&world).count(), 0);
+
+        world.get_mut::<Charge>(ship).unwrap().0 += held;
+        world.get_resource_mut::<Input<KeyCode>>().unwrap().release(KeyCode::Space);
+        stage.run(&mut world);
+        let charged = world.query_filtered::<Entity, With<ChargedLaser>>().iter(&world).count();
+        let normal = world.query_filtered::<Entity, (With<Laser>, Without<ChargedLaser>)>().iter(&world).count();
+        (charged, normal)
+    }
+
+    #[test]
+    fn a_charged_release_fires_only_the_charged_laser() {
+        assert_eq!(release_after(CHARGE_THRESHOLD), (1, 0));
+    }
+
+    #[test]
+    fn a_tap_fires_the_twin_lasers_on_release() {
+        assert_eq!(release_after(0.1), (0, 2));
+    }
+
+    #[test]
+    fn trail_sprites_are_left_where_the_laser_was() {
+        let mut world = World::default();
+        world.insert_resource(Materials { laser_trail: vec![Handle::default()], ..Default::default() });
+        world.insert_resource(Paused(false));
+        let laser = world.spawn().insert_bundle((Laser, FromPlayer, Transform::from_xyz(0., 0., 0.))).id();
+        let mut stage = SystemStage::single(spawn_laser_trail.system());
+        let trail = |world: &mut World| world.query_filtered::<&Transform, With<Fade>>().iter(world).map(|tf| tf.translation).collect::<Vec<_>>();
+
+        stage.run(&mut world);
+        assert!(trail(&mut world).is_empty());
+
+        world.get_mut::<Transform>(laser).unwrap().translation.y = 20.;
+        stage.run(&mut world);
+        assert_eq!(trail(&mut world), vec![Vec3::ZERO]);
     }
 }
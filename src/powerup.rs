@@ -0,0 +1,281 @@
+use std::collections::HashSet;
+
+use bevy::prelude::*;
+use rand::Rng;
+
+use crate::{AppState, GameRng, Materials, Paused, Speed, TimeScale, aabb_collides, player::Player};
+
+const POWERUP_SIZE: f32 = 18.;
+const POWERUP_FALL_SPEED: f32 = 120.;
+const SPEED_BOOST_FACTOR: f32 = 1.6;
+const SPEED_BOOST_TIME: f32 = 5.;
+const SHIELD_HITS: u32 = 1;
+const SPREAD_SHOT_TIME: f32 = 8.;
+const PIERCING_SHOT_TIME: f32 = 8.;
+const BULLET_TIME: f32 = 4.;
+const BULLET_TIME_SCALE: f32 = 0.35;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PowerUpKind {
+    SpeedBoost,
+    Shield,
+    SpreadShot,
+    PiercingShot,
+    BulletTime
+}
+
+pub struct PowerUp {
+    pub kind: PowerUpKind
+}
+
+// dropped by a destroyed enemy, turned into a falling power-up next frame
+pub struct PowerUpToSpawn(pub Vec3);
+
+// hits the player can still absorb
+pub struct Shield(pub u32);
+// drawn over the shielded ship it names
+struct ShieldBubble(Entity);
+// while present the player fires three lasers in a fan
+pub struct SpreadShot(Timer);
+// while present the player's lasers pass through enemies
+pub struct PiercingShot(Timer);
+// kept as a resource rather than on the player so it runs out even if the ship is lost
+struct BulletTime(Timer);
+
+struct SpeedBoost {
+    timer: Timer,
+    base_speed: f32
+}
+
+pub struct PowerUpPlugin;
+
+impl Plugin for PowerUpPlugin {
+    fn build(&self, app: &mut AppBuilder) {
+        app
+            .insert_resource(BulletTime(Timer::from_seconds(BULLET_TIME, false)))
+            .add_system_set(
+            SystemSet::on_update(AppState::InGame)
+            .with_system(powerup_to_spawn.system())
+            .with_system(powerup_movement.system())
+            .with_system(player_collect_powerup.system())
+            .with_system(speed_boost_expire.system())
+            .with_system(shield_bubble.system())
+            .with_system(spread_shot_expire.system())
+            .with_system(piercing_shot_expire.system())
+            .with_system(bullet_time_expire.system())
+        );
+    }
+}
+
+impl PowerUpKind {
+    fn random(rng: &mut impl Rng) -> Self {
+        // bullet time is the rare one
+        match rng.gen_range(0..100) {
+            0..=29 => PowerUpKind::SpeedBoost,
+            30..=54 => PowerUpKind::Shield,
+            55..=74 => PowerUpKind::SpreadShot,
+            75..=91 => PowerUpKind::PiercingShot,
+            _ => PowerUpKind::BulletTime
+        }
+    }
+
+    fn material(&self, materials: &Materials) -> Handle<ColorMaterial> {
+        match self {
+            PowerUpKind::SpeedBoost => materials.powerup_speed.clone(),
+            PowerUpKind::Shield => materials.powerup_shield.clone(),
+            PowerUpKind::SpreadShot => materials.powerup_spread.clone(),
+            PowerUpKind::PiercingShot => materials.powerup_piercing.clone(),
+            PowerUpKind::BulletTime => materials.powerup_bullet_time.clone()
+        }
+    }
+}
+
+fn powerup_to_spawn(
+    mut commands: Commands,
+    materials: Res<Materials>,
+    mut rng: ResMut<GameRng>,
+    query: Query<(Entity, &PowerUpToSpawn)>
+) {
+    for (entity, powerup_to_spawn) in query.iter() {
+        let kind = PowerUpKind::random(&mut rng.rng);
+        commands
+            .spawn_bundle(SpriteBundle {
+                material: kind.material(&materials),
+                sprite: Sprite::new(Vec2::new(POWERUP_SIZE, POWERUP_SIZE)),
+                transform: Transform {
+                    translation: Vec3::new(powerup_to_spawn.0.x, powerup_to_spawn.0.y, 5.),
+                    ..Default::default()
+                },
+                ..Default::default()
+            })
+            .insert(PowerUp { kind });
+
+        commands.entity(entity).despawn();
+    }
+}
+
+fn powerup_movement(
+    time: Res<Time>,
+    paused: Res<Paused>,
+    mut query: Query<&mut Transform, With<PowerUp>>
+) {
+    if paused.0 {
+        return;
+    }
+    for mut tf in query.iter_mut() {
+        tf.translation.y -= POWERUP_FALL_SPEED * time.delta_seconds();
+    }
+}
+
+fn player_collect_powerup(
+    mut commands: Commands,
+    mut time_scale: ResMut<TimeScale>,
+    mut bullet_time: ResMut<BulletTime>,
+    powerup_query: Query<(Entity, &Transform, &Sprite, &PowerUp)>,
+    mut player_query: Query<(Entity, &Transform, &Sprite, &mut Speed, Option<&mut SpeedBoost>), With<Player>>
+) {
+    // in co-op both ships can touch the same drop, the first one gets it
+    let mut collected = HashSet::new();
+    for (player_entity, player_tf, player_sprite, mut speed, speed_boost) in player_query.iter_mut() {
+        for (powerup_entity, powerup_tf, powerup_sprite, powerup) in powerup_query.iter() {
+            if collected.contains(&powerup_entity) || !aabb_collides(powerup_tf, powerup_sprite.size, player_tf, player_sprite.size) {
+                continue;
+            }
+
+            match powerup.kind {
+                PowerUpKind::SpeedBoost => {
+                    // picking up another boost only extends it
+                    if let Some(mut speed_boost) = speed_boost {
+                        speed_boost.timer.reset();
+                    } else {
+                        commands.entity(player_entity).insert(SpeedBoost {
+                            timer: Timer::from_seconds(SPEED_BOOST_TIME, false),
+                            base_speed: speed.0
+                        });
+                        speed.0 *= SPEED_BOOST_FACTOR;
+                    }
+                }
+                PowerUpKind::Shield => {
+                    commands.entity(player_entity).insert(Shield(SHIELD_HITS));
+                }
+                PowerUpKind::SpreadShot => {
+                    // inserting again replaces the old timer
+                    commands.entity(player_entity).insert(SpreadShot(Timer::from_seconds(SPREAD_SHOT_TIME, false)));
+                }
+                PowerUpKind::PiercingShot => {
+                    commands.entity(player_entity).insert(PiercingShot(Timer::from_seconds(PIERCING_SHOT_TIME, false)));
+                }
+                PowerUpKind::BulletTime => {
+                    time_scale.0 = BULLET_TIME_SCALE;
+                    bullet_time.0.reset();
+                }
+            }
+
+            commands.entity(powerup_entity).despawn();
+            collected.insert(powerup_entity);
+            // one pickup per ship per frame keeps the boost bookkeeping simple
+            break;
+        }
+    }
+}
+
+fn speed_boost_expire(
+    mut commands: Commands,
+    time: Res<Time>,
+    paused: Res<Paused>,
+    mut query: Query<(Entity, &mut Speed, &mut SpeedBoost), With<Player>>
+) {
+    if paused.0 {
+        return;
+    }
+    for (entity, mut speed, mut speed_boost) in query.iter_mut() {
+        speed_boost.timer.tick(time.delta());
+        if speed_boost.timer.finished() {
+            speed.0 = speed_boost.base_speed;
+            commands.entity(entity).remove::<SpeedBoost>();
+        }
+    }
+}
+
+fn spread_shot_expire(
+    mut commands: Commands,
+    time: Res<Time>,
+    paused: Res<Paused>,
+    mut query: Query<(Entity, &mut SpreadShot), With<Player>>
+) {
+    if paused.0 {
+        return;
+    }
+    for (entity, mut spread_shot) in query.iter_mut() {
+        spread_shot.0.tick(time.delta());
+        if spread_shot.0.finished() {
+            commands.entity(entity).remove::<SpreadShot>();
+        }
+    }
+}
+
+fn piercing_shot_expire(
+    mut commands: Commands,
+    time: Res<Time>,
+    paused: Res<Paused>,
+    mut query: Query<(Entity, &mut PiercingShot), With<Player>>
+) {
+    if paused.0 {
+        return;
+    }
+    for (entity, mut piercing_shot) in query.iter_mut() {
+        piercing_shot.0.tick(time.delta());
+        if piercing_shot.0.finished() {
+            commands.entity(entity).remove::<PiercingShot>();
+        }
+    }
+}
+
+fn bullet_time_expire(
+    time: Res<Time>,
+    paused: Res<Paused>,
+    mut time_scale: ResMut<TimeScale>,
+    mut bullet_time: ResMut<BulletTime>
+) {
+    if paused.0 || time_scale.0 == 1. {
+        return;
+    }
+    // ticks in real time, the player isn't slowed
+    if bullet_time.0.tick(time.delta()).finished() {
+        time_scale.0 = 1.;
+    }
+}
+
+// keeps a bubble sprite over each player while their shield holds
+fn shield_bubble(
+    mut commands: Commands,
+    materials: Res<Materials>,
+    player_query: Query<(Entity, &Transform), (With<Player>, With<Shield>)>,
+    mut bubble_query: Query<(Entity, &mut Transform, &ShieldBubble), Without<Player>>
+) {
+    let mut covered = HashSet::new();
+    for (bubble_entity, mut bubble_tf, bubble) in bubble_query.iter_mut() {
+        match player_query.get(bubble.0) {
+            Ok((_, player_tf)) => {
+                bubble_tf.translation.x = player_tf.translation.x;
+                bubble_tf.translation.y = player_tf.translation.y;
+                covered.insert(bubble.0);
+            }
+            // the shield ran out or the ship is gone
+            Err(_) => commands.entity(bubble_entity).despawn()
+        }
+    }
+    for (player_entity, player_tf) in player_query.iter().filter(|(entity, _)| !covered.contains(entity)) {
+        commands
+            .spawn_bundle(SpriteBundle {
+                material: materials.shield.clone(),
+                transform: Transform::from_xyz(player_tf.translation.x, player_tf.translation.y, 11.),
+                visible: Visible {
+                    is_visible: true,
+                    is_transparent: true
+                },
+                ..Default::default()
+            })
+            .insert(ShieldBubble(player_entity));
+    }
+}
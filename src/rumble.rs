@@ -0,0 +1,84 @@
+use bevy::prelude::*;
+use gilrs::{Gilrs, ff::{BaseEffect, BaseEffectType, Effect, EffectBuilder, Replay, Repeat, Ticks}};
+
+// a request for one rumble on every connected pad, picked up by play_rumble
+#[derive(Clone, Copy)]
+pub struct RumbleToPlay {
+    strength: f32,
+    duration: f32
+}
+
+impl RumbleToPlay {
+    // short and strong, the player just blew up
+    pub fn death() -> Self {
+        Self { strength: 1., duration: 0.25 }
+    }
+
+    // a long roll for taking down the boss
+    pub fn boss_kill() -> Self {
+        Self { strength: 0.7, duration: 0.9 }
+    }
+}
+
+pub fn rumble(commands: &mut Commands, rumble: RumbleToPlay) {
+    commands.spawn().insert(rumble);
+}
+
+// gilrs stops an effect as soon as its handle drops, so the playing ones are
+// kept here until they run out. The handles aren't Sync, hence non-send.
+struct PlayingRumbles(Vec<(Effect, f64)>);
+
+pub struct RumblePlugin;
+
+impl Plugin for RumblePlugin {
+    fn build(&self, app: &mut AppBuilder) {
+        app
+            .insert_non_send_resource(PlayingRumbles(Vec::new()))
+            .add_system(play_rumble.exclusive_system());
+    }
+}
+
+// exclusive so it can go without when bevy couldn't start gilrs
+fn play_rumble(world: &mut World) {
+    let requests: Vec<(Entity, RumbleToPlay)> = world
+        .query::<(Entity, &RumbleToPlay)>()
+        .iter(world)
+        .map(|(entity, &rumble)| (entity, rumble))
+        .collect();
+    for &(entity, _) in &requests {
+        world.despawn(entity);
+    }
+
+    let now = world.get_resource::<Time>().map_or(0., |time| time.seconds_since_startup());
+    let mut started = Vec::new();
+    if let Some(mut gilrs) = world.get_non_send_resource_mut::<Gilrs>() {
+        let pads: Vec<_> = gilrs
+            .gamepads()
+            .filter(|(_, pad)| pad.is_ff_supported())
+            .map(|(id, _)| id)
+            .collect();
+        if !pads.is_empty() {
+            for (_, rumble) in requests {
+                let duration = Ticks::from_ms((rumble.duration * 1000.) as u32);
+                let effect = EffectBuilder::new()
+                    .add_effect(BaseEffect {
+                        kind: BaseEffectType::Strong { magnitude: (rumble.strength * u16::MAX as f32) as u16 },
+                        scheduling: Replay { play_for: duration, ..Default::default() },
+                        ..Default::default()
+                    })
+                    .gamepads(&pads)
+                    .repeat(Repeat::For(duration))
+                    .finish(&mut gilrs);
+                match effect.and_then(|effect| effect.play().map(|_| effect)) {
+                    Ok(effect) => started.push((effect, now + rumble.duration as f64)),
+                    Err(err) => warn!("Couldn't play rumble: {}", err)
+                }
+            }
+        }
+    }
+
+    if let Some(mut playing) = world.get_non_send_resource_mut::<PlayingRumbles>() {
+        playing.0.retain(|(_, until)| now < *until);
+        playing.0.extend(started);
+    }
+}
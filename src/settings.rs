@@ -0,0 +1,285 @@
+use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::{AppState, FONT, load_json, save_json};
+
+const KEY_BINDINGS_FILE: &str = "keybindings.json";
+const ACTIONS: [&str; 5] = ["Left", "Right", "Up", "Down", "Fire"];
+const ACTION_KEYS: [KeyCode; 5] = [KeyCode::Key1, KeyCode::Key2, KeyCode::Key3, KeyCode::Key4, KeyCode::Key5];
+const ROW_SPACING: f32 = 30.;
+
+// a key for an action, plus an optional second one that works the same
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Binding {
+    pub primary: KeyCode,
+    pub alternate: Option<KeyCode>
+}
+
+impl Binding {
+    pub const fn new(primary: KeyCode, alternate: Option<KeyCode>) -> Self {
+        Self { primary, alternate }
+    }
+
+    pub fn pressed(&self, keyboard_input: &Input<KeyCode>) -> bool {
+        keyboard_input.pressed(self.primary) || self.alternate.map_or(false, |key| keyboard_input.pressed(key))
+    }
+
+    // the same binding without an alternate that's in `keys`
+    pub fn without(self, keys: &[KeyCode]) -> Self {
+        Self { alternate: self.alternate.filter(|key| !keys.contains(key)), ..self }
+    }
+
+    fn get(&self, slot: Slot) -> Option<KeyCode> {
+        match slot {
+            Slot::Primary => Some(self.primary),
+            Slot::Alternate => self.alternate
+        }
+    }
+
+    // the primary key can't be cleared, only replaced
+    fn set(&mut self, slot: Slot, key: Option<KeyCode>) {
+        match (slot, key) {
+            (Slot::Primary, Some(key)) => self.primary = key,
+            (Slot::Primary, None) => {}
+            (Slot::Alternate, key) => self.alternate = key
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Slot {
+    Primary,
+    Alternate
+}
+
+const SLOTS: [Slot; 2] = [Slot::Primary, Slot::Alternate];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct KeyBindings {
+    pub left: Binding,
+    pub right: Binding,
+    pub up: Binding,
+    pub down: Binding,
+    pub fire: Binding
+}
+
+impl Default for KeyBindings {
+    // the arrows and Space, with the WASD and Return alternates from before keys could be rebound
+    fn default() -> Self {
+        Self {
+            left: Binding::new(KeyCode::Left, Some(KeyCode::A)),
+            right: Binding::new(KeyCode::Right, Some(KeyCode::D)),
+            up: Binding::new(KeyCode::Up, Some(KeyCode::W)),
+            down: Binding::new(KeyCode::Down, Some(KeyCode::S)),
+            fire: Binding::new(KeyCode::Space, Some(KeyCode::Return))
+        }
+    }
+}
+
+impl KeyBindings {
+    // same order as ACTIONS
+    fn binding_mut(&mut self, action: usize) -> &mut Binding {
+        match action {
+            0 => &mut self.left,
+            1 => &mut self.right,
+            2 => &mut self.up,
+            3 => &mut self.down,
+            _ => &mut self.fire
+        }
+    }
+
+    fn binding(&self, action: usize) -> Binding {
+        match action {
+            0 => self.left,
+            1 => self.right,
+            2 => self.up,
+            3 => self.down,
+            _ => self.fire
+        }
+    }
+
+    // a key taken elsewhere is swapped over rather than doubled up, unless
+    // there's no key to hand back to a primary slot
+    fn rebind(&mut self, action: usize, slot: Slot, key: KeyCode) {
+        let old = self.binding(action).get(slot);
+        let taken = (0..ACTIONS.len())
+            .flat_map(|other| SLOTS.iter().map(move |&other_slot| (other, other_slot)))
+            .find(|&(other, other_slot)| (other, other_slot) != (action, slot) && self.binding(other).get(other_slot) == Some(key));
+        if let Some((other, other_slot)) = taken {
+            if other_slot == Slot::Primary && old.is_none() {
+                return;
+            }
+            self.binding_mut(other).set(other_slot, old);
+        }
+        self.binding_mut(action).set(slot, Some(key));
+    }
+}
+
+struct SettingsText;
+// the line showing one action's key
+struct BindingRow(usize);
+// the action and slot waiting for a new key, if any
+#[derive(Default)]
+struct Rebinding(Option<(usize, Slot)>);
+pub struct SettingsPlugin;
+
+impl Plugin for SettingsPlugin {
+    fn build(&self, app: &mut AppBuilder) {
+        app
+            .insert_resource(load_json::<KeyBindings>(KEY_BINDINGS_FILE))
+            .insert_resource(Rebinding::default())
+            .add_system_set(
+                SystemSet::on_update(AppState::MainMenu)
+                .with_system(open_settings.system())
+            )
+            .add_system_set(
+                SystemSet::on_enter(AppState::Settings)
+                .with_system(settings_setup.system())
+            )
+            .add_system_set(
+                SystemSet::on_update(AppState::Settings)
+                .with_system(rebind_keys.system())
+                .with_system(update_binding_rows.system())
+            )
+            .add_system_set(
+                SystemSet::on_exit(AppState::Settings)
+                .with_system(settings_cleanup.system())
+            );
+    }
+}
+
+fn open_settings(keyboard_input: Res<Input<KeyCode>>, mut app_state: ResMut<State<AppState>>) {
+    // Return on the same frame may already have queued the game
+    if keyboard_input.just_pressed(KeyCode::K) {
+        let _ = app_state.set(AppState::Settings);
+    }
+}
+
+fn binding_label(bindings: &KeyBindings, rebinding: &Rebinding, action: usize) -> String {
+    let binding = bindings.binding(action);
+    let key_label = |slot: Slot| match (rebinding.0 == Some((action, slot)), binding.get(slot)) {
+        (true, _) => "press a key...".to_string(),
+        (false, Some(key)) => format!("{:?}", key),
+        (false, None) => "-".to_string()
+    };
+    format!("{}  {:<6} {} / {}", action + 1, ACTIONS[action], key_label(Slot::Primary), key_label(Slot::Alternate))
+}
+
+fn settings_setup(
+    mut commands: Commands,
+    asset_server: Res<AssetServer>,
+    bindings: Res<KeyBindings>,
+    mut rebinding: ResMut<Rebinding>
+) {
+    rebinding.0 = None;
+    let font = asset_server.load(FONT);
+    let alignment = TextAlignment {
+        vertical: VerticalAlign::Center,
+        horizontal: HorizontalAlign::Center
+    };
+    let spawn_line = |commands: &mut Commands, value: String, font_size: f32, y: f32| {
+        commands.spawn_bundle(Text2dBundle {
+            text: Text::with_section(
+                value,
+                TextStyle {
+                    font: font.clone(),
+                    font_size,
+                    color: Color::WHITE
+                },
+                alignment
+            ),
+            transform: Transform::from_xyz(0., y, 50.),
+            ..Default::default()
+        })
+        .insert(SettingsText)
+        .id()
+    };
+
+    spawn_line(&mut commands, "KEY BINDINGS".to_string(), 32., 120.);
+    for action in 0..ACTIONS.len() {
+        let row = spawn_line(&mut commands, binding_label(&bindings, &rebinding, action), 20., 60. - action as f32 * ROW_SPACING);
+        commands.entity(row).insert(BindingRow(action));
+    }
+    spawn_line(&mut commands, "1-5 to rebind, Shift+1-5 for the alternate".to_string(), 18., -120.);
+    spawn_line(&mut commands, "Backspace clears an alternate, Esc to go back".to_string(), 18., -145.);
+}
+
+fn rebind_keys(
+    keyboard_input: Res<Input<KeyCode>>,
+    mut bindings: ResMut<KeyBindings>,
+    mut rebinding: ResMut<Rebinding>,
+    mut app_state: ResMut<State<AppState>>
+) {
+    let pressed = match keyboard_input.get_just_pressed().next() {
+        Some(&key) => key,
+        None => return
+    };
+    match rebinding.0 {
+        // Escape backs out of a rebind instead of taking the key
+        Some(_) if pressed == KeyCode::Escape => rebinding.0 = None,
+        Some((action, Slot::Alternate)) if pressed == KeyCode::Back => {
+            bindings.binding_mut(action).set(Slot::Alternate, None);
+            save_json(KEY_BINDINGS_FILE, &*bindings);
+            rebinding.0 = None;
+        }
+        Some((action, slot)) => {
+            bindings.rebind(action, slot, pressed);
+            save_json(KEY_BINDINGS_FILE, &*bindings);
+            rebinding.0 = None;
+        }
+        None if pressed == KeyCode::Escape => {
+            // a transition already queued this frame takes precedence
+            let _ = app_state.set(AppState::MainMenu);
+        }
+        None => {
+            let slot = if keyboard_input.pressed(KeyCode::LShift) || keyboard_input.pressed(KeyCode::RShift) {
+                Slot::Alternate
+            } else {
+                Slot::Primary
+            };
+            rebinding.0 = ACTION_KEYS.iter().position(|&key| key == pressed).map(|action| (action, slot));
+        }
+    }
+}
+
+fn update_binding_rows(
+    bindings: Res<KeyBindings>,
+    rebinding: Res<Rebinding>,
+    mut query: Query<(&mut Text, &BindingRow)>
+) {
+    if bindings.is_changed() || rebinding.is_changed() {
+        for (mut text, row) in query.iter_mut() {
+            text.sections[0].value = binding_label(&bindings, &rebinding, row.0);
+        }
+    }
+}
+
+fn settings_cleanup(mut commands: Commands, query: Query<Entity, With<SettingsText>>) {
+    for entity in query.iter() {
+        commands.entity(entity).despawn();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rebinding_a_taken_key_swaps_it_over() {
+        let mut bindings = KeyBindings::default();
+        // D is right's alternate, left's old alternate A goes there instead
+        bindings.rebind(0, Slot::Alternate, KeyCode::D);
+        assert_eq!(bindings.left, Binding::new(KeyCode::Left, Some(KeyCode::D)));
+        assert_eq!(bindings.right, Binding::new(KeyCode::Right, Some(KeyCode::A)));
+    }
+
+    #[test]
+    fn an_empty_alternate_cant_take_a_primary_key() {
+        let mut bindings = KeyBindings::default();
+        bindings.fire.alternate = None;
+        bindings.rebind(4, Slot::Alternate, KeyCode::Left);
+        assert_eq!(bindings.fire.alternate, None);
+        assert_eq!(bindings.left.primary, KeyCode::Left);
+    }
+}